@@ -1,3 +1,4 @@
+#[allow(dead_code)] // TODO: Temporary until the assembler uses the parser
 mod parser;
 
 #[cfg(test)]
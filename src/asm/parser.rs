@@ -10,9 +10,22 @@ pub fn opcode(input: &[u8]) -> IResult<&[u8], Opcode> {
         value(Hlt, tag_no_case("hlt")),
     ));
 
+    // Sized variants first, so their prefix is not taken for the 64-bit variant
     let load_store = alt((
+        value(Movb, tag_no_case("movb")),
+        value(Movh, tag_no_case("movh")),
+        value(Movw, tag_no_case("movw")),
         value(Mov, tag_no_case("mov")),
+        value(Lodsb, tag_no_case("lodsb")),
+        value(Lodsh, tag_no_case("lodsh")),
+        value(Lodsw, tag_no_case("lodsw")),
+        value(Lodb, tag_no_case("lodb")),
+        value(Lodh, tag_no_case("lodh")),
+        value(Lodw, tag_no_case("lodw")),
         value(Lod, tag_no_case("lod")),
+        value(Strb, tag_no_case("strb")),
+        value(Strh, tag_no_case("strh")),
+        value(Strw, tag_no_case("strw")),
         value(Str, tag_no_case("str")),
     ));
 
@@ -23,6 +36,11 @@ pub fn opcode(input: &[u8]) -> IResult<&[u8], Opcode> {
         value(Div, tag_no_case("div")),
         value(Inc, tag_no_case("inc")),
         value(Dec, tag_no_case("dec")),
+        value(Cmp, tag_no_case("cmp")),
+        value(Imul, tag_no_case("imul")),
+        value(Idiv, tag_no_case("idiv")),
+        value(Mod, tag_no_case("mod")),
+        value(Imod, tag_no_case("imod")),
     ));
 
     let bitwise = alt((
@@ -32,6 +50,8 @@ pub fn opcode(input: &[u8]) -> IResult<&[u8], Opcode> {
         value(Not, tag_no_case("not")),
         value(Shl, tag_no_case("shl")),
         value(Shr, tag_no_case("shr")),
+        value(Tst, tag_no_case("tst")),
+        value(Sar, tag_no_case("sar")),
     ));
 
     let branching = alt((
@@ -44,6 +64,10 @@ pub fn opcode(input: &[u8]) -> IResult<&[u8], Opcode> {
         value(Jle, tag_no_case("jle")),
         value(Jnz, tag_no_case("jnz")),
         value(Jz, tag_no_case("jz")),
+        value(Jsgt, tag_no_case("jsgt")),
+        value(Jslt, tag_no_case("jslt")),
+        value(Jsge, tag_no_case("jsge")),
+        value(Jsle, tag_no_case("jsle")),
     ));
 
     let stack = alt((
@@ -56,5 +80,15 @@ pub fn opcode(input: &[u8]) -> IResult<&[u8], Opcode> {
         value(Cal, tag_no_case("cal")),
     ));
 
-    alt((misc, load_store, arithmetic, bitwise, branching, stack))(input)
+    let interrupts = alt((
+        value(Int, tag_no_case("int")),
+        value(Iret, tag_no_case("iret")),
+        value(Cli, tag_no_case("cli")),
+        value(Sti, tag_no_case("sti")),
+        value(Sys, tag_no_case("sys")),
+    ));
+
+    alt((
+        misc, load_store, arithmetic, bitwise, branching, stack, interrupts,
+    ))(input)
 }
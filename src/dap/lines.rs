@@ -0,0 +1,70 @@
+use std::path::Path;
+
+// An address of an image and the source line it was assembled from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineEntry {
+    pub addr: u64,
+    pub source: String,
+    pub line: u64,
+}
+
+// Maps the addresses of an image to source lines. It is read from a sidecar file next to the image
+// with one entry a line: the address, the source file and the line number, like `0x12 hello.s 4`.
+// Empty lines and lines starting with # are skipped
+// TODO: The table is written by hand until the assembler emits it with the image
+#[derive(Debug, Default)]
+pub struct LineTable {
+    entries: Vec<LineEntry>,
+}
+
+impl LineTable {
+    // Parses a line table, relative source paths are taken relative to base
+    pub fn parse(text: &str, base: &Path) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("Invalid line table entry on line {}: {}", i + 1, line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (addr, source, number) = match fields[..] {
+                [addr, source, number] => (addr, source, number),
+                _ => return Err(invalid()),
+            };
+            let addr = match addr.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => addr.parse(),
+            };
+
+            entries.push(LineEntry {
+                addr: addr.map_err(|_| invalid())?,
+                source: base.join(source).to_string_lossy().into_owned(),
+                line: number.parse().map_err(|_| invalid())?,
+            });
+        }
+
+        entries.sort_by_key(|entry| entry.addr);
+        Ok(Self { entries })
+    }
+
+    // Finds the entry of the line an address belongs to, the last one starting at or before it
+    pub fn lookup(&self, addr: u64) -> Option<&LineEntry> {
+        let after = self.entries.partition_point(|entry| entry.addr <= addr);
+        after.checked_sub(1).map(|i| &self.entries[i])
+    }
+
+    // Finds the first address of a source line, or of the next line with code after it
+    pub fn addr_of(&self, source: &str, line: u64) -> Option<&LineEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| same_source(&entry.source, source) && entry.line >= line)
+            .min_by_key(|entry| (entry.line, entry.addr))
+    }
+}
+
+// Checks if two paths name the same source file, when one of them is relative
+fn same_source(path: &str, other: &str) -> bool {
+    Path::new(path).ends_with(other) || Path::new(other).ends_with(path)
+}
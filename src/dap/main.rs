@@ -0,0 +1,37 @@
+use std::io;
+
+use session::Session;
+
+mod lines;
+mod protocol;
+mod session;
+
+#[cfg(test)]
+mod tests;
+
+fn main() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    // Serve requests until the editor disconnects or closes the stream
+    let mut session = Session::new();
+    while !session.finished() {
+        let request = match protocol::read_message(&mut input) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("Failed to read a request: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        for message in session.handle(&request) {
+            if let Err(err) = protocol::write_message(&mut output, &message) {
+                eprintln!("Failed to write a message: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
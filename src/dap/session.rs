@@ -0,0 +1,427 @@
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use hashbrown::HashMap;
+use serde_json::{json, Value};
+use vm::{
+    dev_map::{bus_fault::BusFault, device::Device},
+    devices::stdout::{STDOUT_NEWLINE, STDOUT_SIZE},
+    register::Register,
+    replay::DEFAULT_CHECKPOINT_INTERVAL,
+    snapshot::Snapshot,
+    ConfigError, Cpu, MachineConfig, StopReason, VmError,
+};
+
+use crate::{lines::LineTable, protocol};
+
+const RAM_SIZE: usize = 1024 * 1024; // 1MB
+const ROM_SIZE: usize = 64 * 1024; // 64KB
+const STDOUT_ADDR: u64 = 0x0000_0CD0_ABF2_D730;
+
+// The CPU is the only thread
+const THREAD_ID: u64 = 1;
+
+// The variables reference of the registers scope
+const REGISTERS: u64 = 1;
+
+// The most instructions a step runs while looking for the next line, in case a line loops forever
+const MAX_LINE_STEPS: u64 = 1_000_000;
+
+// Collects what the guest writes to stdout, so it reaches the editor instead of corrupting the protocol
+struct Console {
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Device for Console {
+    fn read(&self, addr: u64) -> Result<u8, BusFault> {
+        Err(BusFault::read(addr))
+    }
+
+    fn write(&mut self, _addr: u64, value: u8) -> Result<(), BusFault> {
+        match value {
+            STDOUT_NEWLINE => self.output.borrow_mut().push(b'\n'),
+            _ => self.output.borrow_mut().push(value),
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> usize {
+        STDOUT_SIZE
+    }
+}
+
+// The console has no state, what it collected is already sent
+impl Snapshot for Console {}
+
+// How a request resumes the CPU
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    // Step to the next source line, running calls to completion
+    Next,
+    // Step to the next source line, entering calls
+    StepIn,
+    // Run until the current subroutine returns
+    StepOut,
+    // Go back to the previous source line
+    StepBack,
+    // Go back to the last breakpoint hit
+    ReverseContinue,
+}
+
+// A debug session of one image, it turns requests into responses and events
+#[derive(Default)]
+pub struct Session {
+    seq: u64,
+    cpu: Option<Cpu>,
+    lines: LineTable,
+    console: Rc<RefCell<Vec<u8>>>,
+    // Addresses of the breakpoints set in each source file
+    breakpoints: HashMap<String, Vec<u64>>,
+    stop_on_entry: bool,
+    finished: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Checks if the editor disconnected
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    // Handles a request, returns its response followed by the events it caused
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+
+        let mut events = Vec::new();
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsReadMemoryRequest": true,
+                "supportsStepBack": true,
+            })),
+            "launch" => self.launch(args).inspect(|_| {
+                // Breakpoints can be mapped once the line table is loaded
+                events.push(event("initialized", Value::Null));
+            }),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => self.cpu().map(|_| ()).map(|_| {
+                events = match self.stop_on_entry {
+                    true => vec![stopped("entry", None)],
+                    false => self.resume(Resume::Continue),
+                };
+                Value::Null
+            }),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "cpu" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": [{ "name": "Registers", "variablesReference": REGISTERS, "expensive": false }]
+            })),
+            "variables" => self.variables(args),
+            "readMemory" => self.read_memory(args),
+            "continue" | "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue" => {
+                self.cpu().map(|_| ()).map(|_| {
+                    let how = match command {
+                        "continue" => Resume::Continue,
+                        "next" => Resume::Next,
+                        "stepIn" => Resume::StepIn,
+                        "stepOut" => Resume::StepOut,
+                        "stepBack" => Resume::StepBack,
+                        _ => Resume::ReverseContinue,
+                    };
+                    events = self.resume(how);
+                    json!({ "allThreadsContinued": true })
+                })
+            }
+            "disconnect" => {
+                self.finished = true;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("Unsupported request: {}", command)),
+        };
+
+        let mut messages = vec![response(request, result)];
+        messages.extend(events);
+        for message in messages.iter_mut() {
+            self.seq += 1;
+            message["seq"] = json!(self.seq);
+        }
+        messages
+    }
+
+    fn cpu(&mut self) -> Result<&mut Cpu, String> {
+        self.cpu
+            .as_mut()
+            .ok_or_else(|| String::from("No program is launched"))
+    }
+
+    // Loads the image and its line table into a new machine
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or("The launch configuration needs a program")?;
+        let image = std::fs::read(program).map_err(|err| format!("{}: {}", program, err))?;
+
+        // The line table is optional, without it only addresses are shown
+        let lines_path = match args["lines"].as_str() {
+            Some(path) => Path::new(path).to_path_buf(),
+            None => Path::new(program).with_extension("lines"),
+        };
+        self.lines = match std::fs::read_to_string(&lines_path) {
+            Ok(text) => LineTable::parse(&text, lines_path.parent().unwrap_or(Path::new("")))?,
+            Err(_) if args["lines"].is_null() => LineTable::default(),
+            Err(err) => return Err(format!("{}: {}", lines_path.display(), err)),
+        };
+
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        let mut cpu = self
+            .machine(&image)
+            .map_err(|err| format!("{}: {}", program, err))?;
+        cpu.start_recording(DEFAULT_CHECKPOINT_INTERVAL);
+        self.cpu = Some(cpu);
+        Ok(Value::Null)
+    }
+
+    // Creates a CPU with RAM for the stack, the console and the image flashed into ROM at address 0
+    fn machine(&self, image: &[u8]) -> Result<Cpu, ConfigError> {
+        let console = Console {
+            output: self.console.clone(),
+        };
+        MachineConfig::new(RAM_SIZE)
+            .rom("rom", 0, ROM_SIZE, image)
+            .device("stdout", STDOUT_ADDR, Box::new(console))
+            .build()
+    }
+
+    // Replaces the breakpoints of a source file, moving them to the next line with code
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let source = args["source"]["path"]
+            .as_str()
+            .ok_or("The breakpoints need a source path")?;
+        let lines = &self.lines;
+        let cpu = self.cpu.as_mut().ok_or("No program is launched")?;
+
+        // Forget the breakpoints this file had before
+        let addrs = self.breakpoints.entry(String::from(source)).or_default();
+        for addr in addrs.drain(..) {
+            cpu.remove_breakpoint(addr);
+        }
+
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Value> = requested
+            .iter()
+            .map(|breakpoint| {
+                let line = breakpoint["line"].as_u64().unwrap_or_default();
+                match lines.addr_of(source, line) {
+                    Some(entry) => {
+                        cpu.add_breakpoint(entry.addr);
+                        addrs.push(entry.addr);
+                        json!({ "verified": true, "line": entry.line })
+                    }
+                    None => json!({ "verified": false, "line": line, "message": "No code on this line" }),
+                }
+            })
+            .collect();
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    // Returns the frames saved by CAL, newest first
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let frames = self.cpu()?.backtrace();
+        let frames: Vec<Value> = frames
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let mut json = json!({
+                    "id": id,
+                    "name": format!("{:#x}", frame.addr),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#x}", frame.addr),
+                });
+                if let Some(entry) = self.lines.lookup(frame.addr) {
+                    json["line"] = json!(entry.line);
+                    json["column"] = json!(1);
+                    json["source"] = json!({ "path": entry.source });
+                }
+                json
+            })
+            .collect();
+
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    // Returns the registers, in the order of Register::all()
+    fn variables(&mut self, args: &Value) -> Result<Value, String> {
+        if args["variablesReference"].as_u64() != Some(REGISTERS) {
+            return Ok(json!({ "variables": [] }));
+        }
+
+        let cpu = self.cpu()?;
+        let variables: Vec<Value> = Register::all()
+            .iter()
+            .map(|reg| {
+                let value = format!("{:#x}", cpu.reg(*reg));
+                json!({
+                    "name": reg.mnemonic(),
+                    "value": value,
+                    "variablesReference": 0,
+                    "memoryReference": value,
+                })
+            })
+            .collect();
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    // Reads memory for the memory view
+    fn read_memory(&mut self, args: &Value) -> Result<Value, String> {
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        let base = match reference.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => reference.parse(),
+        }
+        .map_err(|_| format!("Invalid memory reference: {}", reference))?;
+        let addr = base.wrapping_add_signed(args["offset"].as_i64().unwrap_or_default());
+        let count = args["count"].as_u64().unwrap_or_default() as usize;
+
+        let bytes = self.cpu()?.read_mem(addr, count);
+        Ok(match bytes {
+            Ok(bytes) => {
+                json!({ "address": format!("{:#x}", addr), "data": protocol::base64(&bytes) })
+            }
+            Err(_) => json!({ "address": format!("{:#x}", addr), "unreadableBytes": count }),
+        })
+    }
+
+    // Resumes the CPU and returns the events telling the editor where it stopped
+    fn resume(&mut self, how: Resume) -> Vec<Value> {
+        let lines = &self.lines;
+        let cpu = match self.cpu.as_mut() {
+            Some(cpu) => cpu,
+            None => return Vec::new(),
+        };
+
+        let result = match how {
+            Resume::Continue => cpu.run(),
+            Resume::Next => step_line(cpu, lines, true),
+            Resume::StepIn => step_line(cpu, lines, false),
+            Resume::StepBack => step_back_line(cpu, lines),
+            Resume::ReverseContinue => cpu.reverse_continue(),
+            Resume::StepOut => cpu.step_out(),
+        };
+
+        let mut events = Vec::new();
+        let output = std::mem::take(&mut *self.console.borrow_mut());
+        if !output.is_empty() {
+            let output = String::from_utf8_lossy(&output);
+            events.push(event(
+                "output",
+                json!({ "category": "stdout", "output": output }),
+            ));
+        }
+
+        match result {
+            Ok(StopReason::Halted) => {
+                events.push(event("exited", json!({ "exitCode": 0 })));
+                events.push(event("terminated", Value::Null));
+            }
+            Ok(StopReason::Breakpoint { .. }) => events.push(stopped("breakpoint", None)),
+            Ok(StopReason::Watchpoint { .. }) => events.push(stopped("data breakpoint", None)),
+            Ok(StopReason::Condition | StopReason::InstructionLimit) => {
+                events.push(stopped("step", None))
+            }
+            Ok(StopReason::RecordingStart) => events.push(stopped("entry", None)),
+            Err(err) => events.push(stopped("exception", Some(err.to_string()))),
+        }
+        events
+    }
+}
+
+// Executes instructions until the CPU reaches another source line, or a single one without line info
+fn step_line(cpu: &mut Cpu, lines: &LineTable, over_calls: bool) -> Result<StopReason, VmError> {
+    let line = |cpu: &Cpu| {
+        lines
+            .lookup(cpu.reg(Register::InstructionPointer))
+            .map(|entry| (entry.source.clone(), entry.line))
+    };
+    let start = line(cpu);
+
+    for _ in 0..MAX_LINE_STEPS {
+        let reason = match over_calls {
+            true => cpu.step_over()?,
+            false => cpu.run_for(1)?,
+        };
+
+        match reason {
+            StopReason::Condition | StopReason::InstructionLimit => {}
+            reason => return Ok(reason),
+        }
+        if start.is_none() || line(cpu) != start {
+            break;
+        }
+    }
+
+    Ok(StopReason::Condition)
+}
+
+// Executes backwards until the CPU reaches another source line, or a single instruction without line info
+fn step_back_line(cpu: &mut Cpu, lines: &LineTable) -> Result<StopReason, VmError> {
+    let line = |cpu: &Cpu| {
+        lines
+            .lookup(cpu.reg(Register::InstructionPointer))
+            .map(|entry| (entry.source.clone(), entry.line))
+    };
+    let start = line(cpu);
+
+    for _ in 0..MAX_LINE_STEPS {
+        let reason = cpu.reverse_step()?;
+        if reason == StopReason::RecordingStart || start.is_none() || line(cpu) != start {
+            return Ok(reason);
+        }
+    }
+
+    Ok(StopReason::Condition)
+}
+
+fn response(request: &Value, result: Result<Value, String>) -> Value {
+    let mut response = json!({
+        "type": "response",
+        "request_seq": request["seq"],
+        "command": request["command"],
+    });
+    match result {
+        Ok(body) => {
+            response["success"] = json!(true);
+            if !body.is_null() {
+                response["body"] = body;
+            }
+        }
+        Err(message) => {
+            response["success"] = json!(false);
+            response["message"] = json!(message);
+        }
+    }
+    response
+}
+
+fn event(name: &str, body: Value) -> Value {
+    let mut event = json!({ "type": "event", "event": name });
+    if !body.is_null() {
+        event["body"] = body;
+    }
+    event
+}
+
+fn stopped(reason: &str, text: Option<String>) -> Value {
+    let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+    if let Some(text) = text {
+        body["text"] = json!(text);
+    }
+    event("stopped", body)
+}
@@ -0,0 +1,216 @@
+use std::path::Path;
+
+use serde_json::{json, Value};
+use vm::opcodes::{AddrMode::*, Opcode::*};
+
+use crate::{lines::LineTable, protocol, session::Session};
+
+const LINES: &str = "
+# addr source line
+0x0c main.s 5
+0x00 main.s 1
+0x0a main.s 2
+0x0e main.s 6
+";
+
+// Sends a request and returns the response followed by the events
+fn request(session: &mut Session, command: &str, arguments: Value) -> Vec<Value> {
+    let request =
+        json!({ "seq": 1, "type": "request", "command": command, "arguments": arguments });
+    let messages = session.handle(&request);
+    assert_eq!(messages[0]["success"], json!(true), "{:?}", messages[0]);
+    messages
+}
+
+// Returns the names of the events among the messages
+fn events(messages: &[Value]) -> Vec<&str> {
+    messages[1..]
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_line_table() {
+    let lines = LineTable::parse(LINES, Path::new("src")).unwrap();
+
+    let entry = lines.lookup(0x0D).unwrap();
+    assert_eq!((entry.addr, entry.line), (0x0C, 5));
+    assert_eq!(
+        entry.source,
+        Path::new("src").join("main.s").to_string_lossy()
+    );
+    assert_eq!(lines.lookup(0x0A).unwrap().line, 2);
+
+    // Lines without code map to the next line with code
+    assert_eq!(lines.addr_of("main.s", 3).unwrap().addr, 0x0C);
+    assert_eq!(lines.addr_of("/work/src/main.s", 6).unwrap().addr, 0x0E);
+    assert!(lines.addr_of("main.s", 7).is_none());
+    assert!(lines.addr_of("other.s", 1).is_none());
+
+    assert!(LineTable::parse("0x00 main.s", Path::new("")).is_err());
+    assert!(LineTable::parse("zz main.s 1", Path::new("")).is_err());
+}
+
+#[test]
+fn test_protocol() {
+    assert_eq!(protocol::base64(b""), "");
+    assert_eq!(protocol::base64(b"f"), "Zg==");
+    assert_eq!(protocol::base64(b"fo"), "Zm8=");
+    assert_eq!(protocol::base64(b"foobar"), "Zm9vYmFy");
+
+    let mut framed = Vec::new();
+    protocol::write_message(&mut framed, &json!({ "seq": 1 })).unwrap();
+    assert_eq!(framed, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+
+    let mut reader = &framed[..];
+    let message = protocol::read_message(&mut reader).unwrap();
+    assert_eq!(message, Some(json!({ "seq": 1 })));
+    assert_eq!(protocol::read_message(&mut reader).unwrap(), None);
+}
+
+#[test]
+fn test_session() {
+    // main calls a subroutine and halts when it returns
+    let program = [
+        &[Cal as u8, Literal as u8][..],
+        &0x0Cu64.to_be_bytes(),
+        &[Hlt as u8, Null as u8],
+        &[Nop as u8, Null as u8],
+        &[Ret as u8, Null as u8],
+    ]
+    .concat();
+
+    let dir = std::env::temp_dir().join(format!("slang-dap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("main.bin"), &program).unwrap();
+    std::fs::write(dir.join("main.lines"), LINES).unwrap();
+    let source = dir.join("main.s").to_string_lossy().into_owned();
+
+    let mut session = Session::new();
+    request(&mut session, "initialize", json!({}));
+    let messages = request(
+        &mut session,
+        "launch",
+        json!({ "program": dir.join("main.bin").to_string_lossy() }),
+    );
+    assert_eq!(events(&messages), ["initialized"]);
+
+    // A breakpoint on a line without code moves to the next line with code
+    let messages = request(
+        &mut session,
+        "setBreakpoints",
+        json!({ "source": { "path": source }, "breakpoints": [{ "line": 4 }, { "line": 9 }] }),
+    );
+    let breakpoints = &messages[0]["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], json!(true));
+    assert_eq!(breakpoints[0]["line"], json!(5));
+    assert_eq!(breakpoints[1]["verified"], json!(false));
+
+    let messages = request(&mut session, "configurationDone", json!({}));
+    assert_eq!(events(&messages), ["stopped"]);
+    assert_eq!(messages[1]["body"]["reason"], json!("breakpoint"));
+
+    // The subroutine and the caller it returns to
+    let messages = request(&mut session, "stackTrace", json!({ "threadId": 1 }));
+    let frames = &messages[0]["body"]["stackFrames"];
+    assert_eq!(frames[0]["line"], json!(5));
+    assert_eq!(frames[1]["line"], json!(2));
+    assert_eq!(frames[0]["source"]["path"], json!(source));
+
+    let messages = request(&mut session, "next", json!({ "threadId": 1 }));
+    assert_eq!(messages[1]["body"]["reason"], json!("step"));
+    let messages = request(
+        &mut session,
+        "variables",
+        json!({ "variablesReference": 1 }),
+    );
+    let variables = messages[0]["body"]["variables"].as_array().unwrap();
+    let ip = variables.iter().find(|var| var["name"] == "ip").unwrap();
+    assert_eq!(ip["value"], json!("0xe"));
+
+    request(&mut session, "stepOut", json!({ "threadId": 1 }));
+    let messages = request(&mut session, "stackTrace", json!({ "threadId": 1 }));
+    assert_eq!(messages[0]["body"]["stackFrames"][0]["line"], json!(2));
+
+    let messages = request(
+        &mut session,
+        "readMemory",
+        json!({ "memoryReference": "0x0", "count": 2 }),
+    );
+    assert_eq!(
+        messages[0]["body"]["data"],
+        json!(protocol::base64(&[Cal as u8, Literal as u8]))
+    );
+
+    let messages = request(&mut session, "continue", json!({ "threadId": 1 }));
+    assert_eq!(events(&messages), ["exited", "terminated"]);
+
+    request(&mut session, "disconnect", json!({}));
+    assert!(session.finished());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_step_over_recursive_call() {
+    use vm::register::Register::Accumulator;
+
+    // main calls f(3), f counts the accumulator down and calls itself until it is zero
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &3u64.to_be_bytes(),
+        &[Accumulator as u8],
+        &[Cal as u8, Literal as u8],
+        &0x17u64.to_be_bytes(),
+        &[Hlt as u8, Null as u8],
+        // 0x17
+        &[Sub as u8, ImmToReg as u8],
+        &1u64.to_be_bytes(),
+        &[Accumulator as u8],
+        &[Jz as u8, Literal as u8],
+        &0x36u64.to_be_bytes(),
+        &[Cal as u8, Literal as u8],
+        &0x17u64.to_be_bytes(),
+        // 0x36
+        &[Ret as u8, Null as u8],
+    ]
+    .concat();
+    let lines =
+        "0x00 f.s 1\n0x0b f.s 2\n0x15 f.s 3\n0x17 f.s 5\n0x22 f.s 6\n0x2c f.s 7\n0x36 f.s 8";
+
+    let dir = std::env::temp_dir().join(format!("slang-dap-recursion-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("f.bin"), &program).unwrap();
+    std::fs::write(dir.join("f.lines"), lines).unwrap();
+    let source = dir.join("f.s").to_string_lossy().into_owned();
+
+    let mut session = Session::new();
+    request(&mut session, "initialize", json!({}));
+    request(
+        &mut session,
+        "launch",
+        json!({ "program": dir.join("f.bin").to_string_lossy() }),
+    );
+    request(
+        &mut session,
+        "setBreakpoints",
+        json!({ "source": { "path": source }, "breakpoints": [{ "line": 7 }] }),
+    );
+    request(&mut session, "configurationDone", json!({}));
+    request(
+        &mut session,
+        "setBreakpoints",
+        json!({ "source": { "path": source }, "breakpoints": [] }),
+    );
+
+    // Stepping over the recursive call in f(2) stops when it returns, not when the inner f(0) returns
+    let messages = request(&mut session, "next", json!({ "threadId": 1 }));
+    assert_eq!(messages[1]["body"]["reason"], json!("step"));
+    let messages = request(&mut session, "stackTrace", json!({ "threadId": 1 }));
+    let frames = messages[0]["body"]["stackFrames"].as_array().unwrap();
+    let lines: Vec<&Value> = frames.iter().map(|frame| &frame["line"]).collect();
+    assert_eq!(lines, [&json!(8), &json!(3)]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
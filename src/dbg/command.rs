@@ -0,0 +1,113 @@
+use vm::register::Register;
+
+// The number of bytes x shows and instructions disas decodes without a count
+pub const DEFAULT_COUNT: u64 = 16;
+
+// A command of the debugger REPL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    // Execute n instructions, entering calls
+    Step(u64),
+    // Execute one instruction, running calls to completion
+    Next,
+    // Run until the CPU halts or stops at a breakpoint or watchpoint
+    Continue,
+    // Go back n instructions
+    ReverseStep(u64),
+    // Go back to the last breakpoint hit, or the start of the recording
+    ReverseContinue,
+    Break(u64),
+    Delete(u64),
+    Regs,
+    // Show count bytes of memory at an address
+    Examine { count: u64, addr: u64 },
+    // Decode count instructions at an address, or at the instruction pointer
+    Disas { addr: Option<u64>, count: u64 },
+    Backtrace,
+    Set { reg: Register, value: u64 },
+    Help,
+    Quit,
+}
+
+// Parses a line of input into a command
+pub fn parse(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let name = words.next().ok_or("Empty command")?;
+    let args: Vec<&str> = words.collect();
+
+    // x takes its count after a slash, like x/16
+    if let Some(count) = name.strip_prefix("x/") {
+        let count = number(count)?;
+        return match args[..] {
+            [addr] => Ok(Command::Examine {
+                count,
+                addr: number(addr)?,
+            }),
+            _ => Err(String::from("Usage: x/<count> <addr>")),
+        };
+    }
+
+    let command = match (name, &args[..]) {
+        ("step" | "s", []) => Command::Step(1),
+        ("step" | "s", [n]) => Command::Step(number(n)?),
+        ("next" | "n", []) => Command::Next,
+        ("continue" | "c", []) => Command::Continue,
+        ("reverse-step" | "rs", []) => Command::ReverseStep(1),
+        ("reverse-step" | "rs", [n]) => Command::ReverseStep(number(n)?),
+        ("reverse-continue" | "rc", []) => Command::ReverseContinue,
+        ("break" | "b", [addr]) => Command::Break(number(addr)?),
+        ("delete" | "d", [addr]) => Command::Delete(number(addr)?),
+        ("regs" | "r", []) => Command::Regs,
+        ("x", [addr]) => Command::Examine {
+            count: DEFAULT_COUNT,
+            addr: number(addr)?,
+        },
+        ("disas", []) => Command::Disas {
+            addr: None,
+            count: DEFAULT_COUNT,
+        },
+        ("disas", [addr]) => Command::Disas {
+            addr: Some(number(addr)?),
+            count: DEFAULT_COUNT,
+        },
+        ("disas", [addr, count]) => Command::Disas {
+            addr: Some(number(addr)?),
+            count: number(count)?,
+        },
+        ("bt", []) => Command::Backtrace,
+        ("set", ["reg", reg, value]) | ("set", [reg, value]) => Command::Set {
+            reg: Register::from_mnemonic(reg).ok_or(format!("Unknown register: {}", reg))?,
+            value: number(value)?,
+        },
+        ("help" | "h", []) => Command::Help,
+        ("quit" | "q", []) => Command::Quit,
+        _ => return Err(format!("Invalid command: {}, try help", line.trim())),
+    };
+
+    Ok(command)
+}
+
+// Parses a hexadecimal number prefixed with 0x or a decimal number
+pub fn number(text: &str) -> Result<u64, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid number: {}", text))
+}
+
+pub const HELP: &str = "\
+step [n]            Execute n instructions, entering calls
+next                Execute one instruction, running calls to completion
+continue            Run until a breakpoint, a watchpoint or HLT
+reverse-step [n]    Go back n instructions
+reverse-continue    Go back to the last breakpoint hit or the start of the run
+break <addr>        Stop before the instruction at addr
+delete <addr>       Remove the breakpoint at addr
+regs                Show the registers
+x/<n> <addr>        Show n bytes of memory at addr
+disas [addr] [n]    Decode n instructions at addr or at the instruction pointer
+bt                  Show the saved frames
+set reg <reg> <v>   Overwrite a register
+quit                Exit the debugger
+An empty line repeats the last command.";
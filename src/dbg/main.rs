@@ -0,0 +1,228 @@
+use std::io::{self, BufRead, Write};
+
+use command::Command;
+use vm::{
+    devices::stdout::Stdout,
+    machine_file::{DeviceRegistry, MachineFile},
+    register::Register,
+    replay::DEFAULT_CHECKPOINT_INTERVAL,
+    Cpu, MachineConfig, StopReason, VmError,
+};
+
+mod command;
+
+#[cfg(test)]
+mod tests;
+
+const RAM_SIZE: usize = 1024 * 1024; // 1MB
+const ROM_SIZE: usize = 64 * 1024; // 64KB
+const STDOUT_ADDR: u64 = 0x0000_0CD0_ABF2_D730;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (path, gdb) = match &args[..] {
+        [path] => (path, None),
+        [path, flag, addr] if flag == "--gdb" => (path, Some(addr)),
+        _ => {
+            eprintln!("Usage: slang-dbg <rom image | machine.toml> [--gdb <addr:port>]");
+            std::process::exit(1);
+        }
+    };
+
+    // Record the run so it can be executed in reverse
+    let mut cpu = match machine(path) {
+        Ok(cpu) => cpu,
+        Err(err) => {
+            eprintln!("Failed to load {}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+    cpu.start_recording(DEFAULT_CHECKPOINT_INTERVAL);
+
+    // Let gdb drive the CPU instead of the REPL
+    if let Some(addr) = gdb {
+        println!("Waiting for gdb on {}", addr);
+        if let Err(err) = vm::gdb::listen(addr.as_str(), &mut cpu) {
+            eprintln!("gdb session failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    println!("Loaded {}, type help for the commands", path);
+    print_next(&cpu);
+
+    // Read commands until quit or the end of the input
+    let mut last = None;
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("(slang-dbg) ");
+        io::stdout().flush().unwrap();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+
+        // An empty line repeats the last command
+        let command = match (line.trim(), last) {
+            ("", Some(command)) => command,
+            ("", None) => continue,
+            (line, _) => match command::parse(line) {
+                Ok(command) => command,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    continue;
+                }
+            },
+        };
+        last = Some(command);
+
+        if command == Command::Quit {
+            break;
+        }
+        if let Err(err) = execute(&mut cpu, command) {
+            eprintln!("CPU fault: {}", err);
+        }
+    }
+}
+
+// Creates the machine a machine file describes, or a CPU with RAM for the stack, stdout and the
+// image flashed into ROM at address 0
+fn machine(path: &str) -> Result<Cpu, String> {
+    if path.ends_with(".toml") {
+        let file = MachineFile::load(path).map_err(|err| err.to_string())?;
+        return file
+            .build(&DeviceRegistry::default())
+            .map_err(|err| err.to_string());
+    }
+
+    let image = std::fs::read(path).map_err(|err| err.to_string())?;
+    MachineConfig::new(RAM_SIZE)
+        .rom("rom", 0, ROM_SIZE, &image)
+        .device("stdout", STDOUT_ADDR, Box::new(Stdout::new()))
+        .build()
+        .map_err(|err| err.to_string())
+}
+
+// Executes a debugger command
+fn execute(cpu: &mut Cpu, command: Command) -> Result<(), VmError> {
+    match command {
+        Command::Step(n) => {
+            for _ in 0..n {
+                if cpu.step()?.halted() {
+                    println!("Halted");
+                    break;
+                }
+            }
+            print_next(cpu);
+        }
+        Command::Next => {
+            let reason = cpu.step_over()?;
+            print_stop(reason);
+            print_next(cpu);
+        }
+        Command::Continue => {
+            let reason = cpu.run()?;
+            print_stop(reason);
+            print_next(cpu);
+        }
+        Command::ReverseStep(n) => {
+            for _ in 0..n {
+                if cpu.reverse_step()? == StopReason::RecordingStart {
+                    print_stop(StopReason::RecordingStart);
+                    break;
+                }
+            }
+            print_next(cpu);
+        }
+        Command::ReverseContinue => {
+            let reason = cpu.reverse_continue()?;
+            print_stop(reason);
+            print_next(cpu);
+        }
+        Command::Break(addr) => {
+            cpu.add_breakpoint(addr);
+            println!("Breakpoint at {:#x}", addr);
+        }
+        Command::Delete(addr) => {
+            cpu.remove_breakpoint(addr);
+            println!("Deleted the breakpoint at {:#x}", addr);
+        }
+        Command::Regs => {
+            for (reg, value) in cpu.registers().iter() {
+                println!("{:<6} {:#018x} {}", reg.mnemonic(), value, value);
+            }
+        }
+        Command::Examine { count, addr } => examine(cpu, addr, count),
+        Command::Disas { addr, count } => {
+            let addr = addr.unwrap_or_else(|| cpu.reg(Register::InstructionPointer));
+            disassemble(cpu, addr, count);
+        }
+        Command::Backtrace => {
+            for (i, frame) in cpu.backtrace().iter().enumerate() {
+                println!(
+                    "#{:<3} {:#010x} fp={:#x}",
+                    i, frame.addr, frame.frame_pointer
+                );
+            }
+        }
+        Command::Set { reg, value } => {
+            cpu.set_reg(reg, value);
+            println!("{} = {:#x}", reg.mnemonic(), value);
+        }
+        Command::Help => println!("{}", command::HELP),
+        Command::Quit => {}
+    }
+
+    Ok(())
+}
+
+// Prints why a run stopped
+fn print_stop(reason: StopReason) {
+    match reason {
+        StopReason::Halted => println!("Halted"),
+        StopReason::Breakpoint { addr } => println!("Breakpoint at {:#x}", addr),
+        StopReason::Watchpoint { addr, access } => {
+            println!("Watchpoint on {} at {:#x}", access, addr)
+        }
+        StopReason::RecordingStart => println!("At the start of the recording"),
+        StopReason::InstructionLimit | StopReason::Condition => {}
+    }
+}
+
+// Prints the instruction that executes next
+fn print_next(cpu: &Cpu) {
+    disassemble(cpu, cpu.reg(Register::InstructionPointer), 1);
+}
+
+// Prints count instructions decoded at an address, marking the one at the instruction pointer
+fn disassemble(cpu: &Cpu, mut addr: u64, count: u64) {
+    let ip = cpu.reg(Register::InstructionPointer);
+    for _ in 0..count {
+        let marker = if addr == ip { "=>" } else { "  " };
+        match cpu.decode(addr) {
+            Ok((instr, len)) => {
+                println!("{} {:#010x}: {:?}", marker, addr, instr);
+                addr = addr.wrapping_add(len);
+            }
+            Err(err) => {
+                println!("{} {:#010x}: {}", marker, addr, err);
+                break;
+            }
+        }
+    }
+}
+
+// Prints count bytes of memory at an address, 16 bytes a line
+fn examine(cpu: &mut Cpu, addr: u64, count: u64) {
+    match cpu.read_mem(addr, count as usize) {
+        Ok(bytes) => {
+            for (i, line) in bytes.chunks(16).enumerate() {
+                let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+                println!("{:#010x}: {}", addr + i as u64 * 16, hex.join(" "));
+            }
+        }
+        Err(err) => eprintln!("{}", err),
+    }
+}
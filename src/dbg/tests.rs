@@ -0,0 +1,95 @@
+use crate::{
+    command::{parse, Command, DEFAULT_COUNT},
+    execute,
+};
+use vm::{
+    opcodes::{AddrMode::*, Opcode::*},
+    register::Register,
+    MachineConfig,
+};
+
+#[test]
+fn test_parse() {
+    assert_eq!(parse("step"), Ok(Command::Step(1)));
+    assert_eq!(parse("s 5"), Ok(Command::Step(5)));
+    assert_eq!(parse("b 0x2a"), Ok(Command::Break(0x2A)));
+    assert_eq!(parse("rs 3"), Ok(Command::ReverseStep(3)));
+    assert_eq!(parse("reverse-continue"), Ok(Command::ReverseContinue));
+    assert_eq!(
+        parse("x/32 0x800"),
+        Ok(Command::Examine {
+            count: 32,
+            addr: 0x800,
+        })
+    );
+    assert_eq!(
+        parse("disas"),
+        Ok(Command::Disas {
+            addr: None,
+            count: DEFAULT_COUNT,
+        })
+    );
+    assert_eq!(
+        parse("set reg R0 10"),
+        Ok(Command::Set {
+            reg: Register::Reg0,
+            value: 10,
+        })
+    );
+    assert_eq!(
+        parse("set sp 0x100"),
+        Ok(Command::Set {
+            reg: Register::StackPointer,
+            value: 0x100,
+        })
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(parse("").is_err());
+    assert!(parse("x/abc 0x800").is_err());
+    assert!(parse("set reg r9 1").is_err());
+    assert!(parse("break").is_err());
+    assert!(parse("jump 0x10").is_err());
+}
+
+#[test]
+fn test_next_over_recursive_call() {
+    use Register::Accumulator;
+
+    // main calls f(3), f counts the accumulator down and calls itself until it is zero
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &3u64.to_be_bytes(),
+        &[Accumulator as u8],
+        &[Cal as u8, Literal as u8],
+        &0x17u64.to_be_bytes(),
+        &[Hlt as u8, Null as u8],
+        // 0x17
+        &[Sub as u8, ImmToReg as u8],
+        &1u64.to_be_bytes(),
+        &[Accumulator as u8],
+        &[Jz as u8, Literal as u8],
+        &0x36u64.to_be_bytes(),
+        // 0x2c
+        &[Cal as u8, Literal as u8],
+        &0x17u64.to_be_bytes(),
+        // 0x36
+        &[Ret as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = MachineConfig::new(0x1000)
+        .rom("rom", 0, 0x100, &program)
+        .build()
+        .unwrap();
+    execute(&mut cpu, Command::Break(0x2C)).unwrap();
+    execute(&mut cpu, Command::Continue).unwrap();
+    execute(&mut cpu, Command::Delete(0x2C)).unwrap();
+    assert_eq!(cpu.backtrace().len(), 2);
+
+    // next on the recursive call in f(2) stops when it returns, not when the inner f(0) returns
+    execute(&mut cpu, Command::Next).unwrap();
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0x36);
+    assert_eq!(cpu.backtrace().len(), 2);
+}
@@ -1,11 +1,12 @@
 use vm::{
     devices::{
-        rom::Rom,
         stdout::{Stdout, STDOUT_NEWLINE},
+        RamKind,
     },
+    machine_file::{DeviceRegistry, MachineFile, MachineFileError},
     opcodes::AddrMode::*,
     opcodes::Opcode::*,
-    Cpu,
+    ConfigError, Cpu, MachineConfig,
 };
 
 const MEM_SIZE: usize = 1024 * 1024 * 1024 * 4; // 4GB
@@ -13,22 +14,38 @@ const ROM_SIZE: usize = 1024 * 1024; // 1MB
 const STDOUT_ADDR: u64 = 0x0000_0CD0_ABF2_D730;
 
 fn main() {
-    // Create CPU
-    let mut cpu = Cpu::new(MEM_SIZE);
-
-    // Attach ROM
-    let rom = Box::new(rom());
-    cpu.attach(rom, String::from("rom"), 0);
-
-    // Attach stdout
-    let stdout = Box::new(Stdout::new());
-    cpu.attach(stdout, String::from("stdout"), STDOUT_ADDR);
+    // Create CPU from the machine file if one is given, otherwise with the program in ROM and stdout
+    let cpu = match std::env::args().nth(1) {
+        Some(path) => {
+            MachineFile::load(&path).and_then(|file| file.build(&DeviceRegistry::default()))
+        }
+        None => hello_world().map_err(MachineFileError::from),
+    };
+    let mut cpu = match cpu {
+        Ok(cpu) => cpu,
+        Err(err) => {
+            eprintln!("Invalid machine: {}", err);
+            std::process::exit(1);
+        }
+    };
 
     // Run CPU
-    cpu.run();
+    if let Err(err) = cpu.run() {
+        eprintln!("CPU fault: {}", err);
+        std::process::exit(1);
+    }
+}
+
+// The RAM is sparse so only the pages in use are allocated
+fn hello_world() -> Result<Cpu, ConfigError> {
+    MachineConfig::new(MEM_SIZE)
+        .ram_kind(RamKind::Sparse)
+        .rom("rom", 0, ROM_SIZE, &program())
+        .device("stdout", STDOUT_ADDR, Box::new(Stdout::new()))
+        .build()
 }
 
-fn rom() -> Rom {
+fn program() -> Vec<u8> {
     // Stdout constants as bytes
     let stdout = STDOUT_ADDR.to_be_bytes();
     let new_line = (STDOUT_NEWLINE as u64).to_be_bytes();
@@ -36,26 +53,22 @@ fn rom() -> Rom {
     #[rustfmt::skip]
     let program = vec![
         // Print "Hello, World!"
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'H' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'e' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'l' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'l' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'o' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, ',' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, ' ' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'W' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'o' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'r' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'l' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, 'd' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, '!' as u8, stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
-        Mov as u8, ImmToMem as u8, new_line[0], new_line[1], new_line[2], new_line[3], new_line[4], new_line[5], new_line[6], new_line[7], stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'H', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'e', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'l', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'l', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'o', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b',', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b' ', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'W', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'o', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'r', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'l', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'd', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, 0x00, 0x00, 0x00, 0x00 ,0x00, 0x00, 0x00, b'!', stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
+        Movb as u8, ImmToMem as u8, new_line[0], new_line[1], new_line[2], new_line[3], new_line[4], new_line[5], new_line[6], new_line[7], stdout[0], stdout[1], stdout[2], stdout[3], stdout[4], stdout[5], stdout[6], stdout[7],
         Hlt as u8, Null as u8,
     ];
 
-    let mut rom = Rom::new(ROM_SIZE);
-
-    rom.flash(&program);
-
-    rom
+    program
 }
@@ -1,22 +1,57 @@
 use super::{
-    dev_map::{device::Device, device_mapper::DeviceMapper},
-    devices::{ram::Ram, registers::Registers},
-    opcodes::{AddrMode, Instruction, Opcode, Operand},
-    register::Register,
+    dev_map::{
+        bus_fault::BusFault,
+        device::{Device, DEFAULT_TICK_INTERVAL},
+        device_mapper::DeviceMapper,
+        history::BusHistory,
+        permissions::Permissions,
+        watchpoint::{WatchKind, Watchpoint},
+    },
+    devices::{registers::Registers, RamKind, RAM_NAME},
+    error::{Fault, VmError},
+    opcodes::{AddrMode, Instruction, Opcode, Operand, Width},
+    register::{flags, Register, RegisterSnapshot},
+    replay::{Checkpoint, Recorder, ReplayLog, SyscallRecord},
+    snapshot::{SaveState, SnapshotError},
+    step::{BreakCondition, Flow, Frame, StepOutcome, StopReason},
+    syscall::{CpuContext, SyscallHandler},
+    trace::{TraceRecord, Tracer},
+    vectors,
 };
 use hashbrown::HashMap;
 
+// Offsets from the frame pointer of a frame saved by push_state, the frame pointer points at the free slot below it
+const FRAME_SAVED_FP: u64 = 2 * 8;
+const FRAME_SAVED_IP: u64 = 11 * 8;
+
+// The most frames a backtrace walks, in case the saved frame pointers form a loop
+const MAX_BACKTRACE: usize = 256;
+
 pub struct Cpu {
     running: bool,
     regs: Registers,
     regs_names: Vec<Register>,
     pub dev_mapper: DeviceMapper,
     regs_addr_map: HashMap<Register, u64>,
+    syscalls: HashMap<u64, SyscallHandler>,
+    breakpoints: HashMap<u64, Option<BreakCondition>>,
+    tracer: Option<Tracer>,
+    recorder: Option<Recorder>,
+    // The number of instructions executed, every instruction is one cycle
+    cycles: u64,
+    tick_interval: u64,
 }
 
 // public methods
 impl Cpu {
+    // Creates a CPU with dense RAM mapped at 0, without checking the layout like MachineConfig does
+    #[deprecated(note = "use MachineConfig::new(mem_size).build(), which checks the layout")]
     pub fn new(mem_size: usize) -> Self {
+        Self::with_ram(mem_size, RamKind::Dense)
+    }
+
+    // Creates a CPU with RAM of the given kind mapped at 0, MachineConfig checks the layout before calling it
+    pub(crate) fn with_ram(mem_size: usize, ram_kind: RamKind) -> Self {
         // Get all the registers
         let regs_names = Register::all();
 
@@ -34,18 +69,22 @@ impl Cpu {
             regs.write(*regs_addr_map.get(reg).unwrap(), 0x00);
         }
 
-        // Set the stack pointer to the end of the memory
+        // Set the stack pointer to the last stack slot of the memory, the first push writes it
         regs.write(
             *regs_addr_map.get(&Register::StackPointer).unwrap(),
-            mem_size as u64,
+            (mem_size as u64).saturating_sub(std::mem::size_of::<u64>() as u64),
         );
 
         // Create device mapper
         let mut dev_mapper = DeviceMapper::new();
 
         // Map the RAM
-        let ram = Box::new(Ram::new(mem_size));
-        dev_mapper.map(ram, "RAM".to_owned(), 0x00);
+        dev_mapper.map(
+            ram_kind.create(mem_size),
+            RAM_NAME.to_owned(),
+            0x00,
+            Permissions::ALL,
+        );
 
         // Return the CPU
         Self {
@@ -53,26 +92,410 @@ impl Cpu {
             regs_names,
             regs_addr_map,
             running: false,
-            dev_mapper: DeviceMapper::new(),
+            dev_mapper,
+            syscalls: HashMap::new(),
+            breakpoints: HashMap::new(),
+            tracer: None,
+            recorder: None,
+            cycles: 0,
+            tick_interval: DEFAULT_TICK_INTERVAL,
+        }
+    }
+
+    // Run the CPU until it halts or faults
+    pub fn run(&mut self) -> Result<StopReason, VmError> {
+        self.run_until(|_| false)
+    }
+
+    // Run the CPU for at most n instructions
+    pub fn run_for(&mut self, n: u64) -> Result<StopReason, VmError> {
+        self.running = true;
+
+        for i in 0..n {
+            // A breakpoint at the address the run resumes from was already hit
+            if i > 0 {
+                if let Some(reason) = self.check_breakpoint() {
+                    return Ok(reason);
+                }
+            }
+
+            if self.step()?.halted() {
+                return Ok(StopReason::Halted);
+            }
+            if let Some(reason) = self.check_watchpoint() {
+                return Ok(reason);
+            }
         }
+
+        Ok(StopReason::InstructionLimit)
     }
 
-    // Run the CPU
-    pub fn run(&mut self) {
+    // Run the CPU until it halts, faults, stops at a breakpoint or watchpoint
+    // or the predicate matches an executed instruction
+    pub fn run_until<F>(&mut self, mut predicate: F) -> Result<StopReason, VmError>
+    where
+        F: FnMut(&StepOutcome) -> bool,
+    {
         self.running = true;
 
+        let mut resumed = true;
         while self.running {
-            // Fetch the instruction
-            let instr = self.fetch();
+            // A breakpoint at the address the run resumes from was already hit
+            if !resumed {
+                if let Some(reason) = self.check_breakpoint() {
+                    return Ok(reason);
+                }
+            }
+            resumed = false;
+
+            let outcome = self.step()?;
+            if let Some(reason) = self.check_watchpoint() {
+                return Ok(reason);
+            }
+            if predicate(&outcome) {
+                return Ok(StopReason::Condition);
+            }
+        }
 
-            // Execute the instruction
-            self.execute(instr);
+        Ok(StopReason::Halted)
+    }
+
+    // Execute the next instruction, a call runs until it returns to the instruction after it.
+    // Recursive calls that return to the same address return first
+    pub fn step_over(&mut self) -> Result<StopReason, VmError> {
+        let (instr, _) = self.decode(self.read_reg(Register::InstructionPointer))?;
+        match instr.opcode {
+            Opcode::Cal => self.run_out_of(0),
+            _ => self.run_for(1),
+        }
+    }
+
+    // Run the CPU until the subroutine it is in returns, the calls it makes on the way return first
+    pub fn step_out(&mut self) -> Result<StopReason, VmError> {
+        self.run_out_of(1)
+    }
+
+    // Fetch and execute exactly one instruction, entering the handler of a pending interrupt first
+    pub fn step(&mut self) -> Result<StepOutcome, VmError> {
+        // Only report the accesses of this instruction
+        self.dev_mapper.take_watch_hit();
+
+        self.checkpoint();
+        let mut interrupt = self.interrupt()?;
+
+        // Fetch the instruction, a bus fault enters its handler and the handler's first instruction is fetched instead.
+        // The handler of an interrupt taken above can be the one that faulted, its vector is kept
+        let mut addr = self.read_reg(Register::InstructionPointer);
+        let mut fetch_fault = None;
+        let instr = match self.fetch() {
+            Err(
+                err @ VmError::BusFault {
+                    addr: fault, kind, ..
+                },
+            ) => {
+                match self.trap_bus_fault(BusFault { addr: fault, kind }) {
+                    Ok(true) => {
+                        interrupt.get_or_insert(vectors::BUS_FAULT);
+                        fetch_fault = Some(fault);
+                    }
+                    _ => return Err(err),
+                }
+                addr = self.read_reg(Register::InstructionPointer);
+                self.fetch()?
+            }
+            result => result?,
+        };
+        let next = self.read_reg(Register::InstructionPointer);
+
+        // Execute the instruction
+        let trapped = self.execute(addr, instr.clone())?;
+
+        // Work out where the instruction sent the control flow
+        let target = self.read_reg(Register::InstructionPointer);
+        let flow = match (trapped, instr.opcode) {
+            (Some(vector), _) => Flow::Trap { vector, target },
+            (None, Opcode::Hlt) => Flow::Halt,
+            (None, Opcode::Cal) => Flow::Call { target },
+            (None, Opcode::Ret | Opcode::Iret) => Flow::Return { target },
+            (None, Opcode::Int) => match instr.operands.0 {
+                Operand::Imm(vector) => Flow::Trap { vector, target },
+                _ => Flow::Branch { target },
+            },
+            _ if target != next => Flow::Branch { target },
+            _ => Flow::Next,
+        };
+
+        self.cycles += 1;
+        self.tick();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.advance();
         }
+
+        Ok(StepOutcome {
+            addr,
+            instr,
+            flow,
+            interrupt,
+            fetch_fault,
+        })
+    }
+
+    // Decode the instruction at an address without executing it, returns it with its length in bytes
+    pub fn decode(&self, addr: u64) -> Result<(Instruction, u64), VmError> {
+        let bus_fault = |fault: BusFault| VmError::BusFault {
+            ip: addr,
+            addr: fault.addr,
+            kind: fault.kind,
+        };
+        let mut cursor = addr;
+
+        // Fetch the opcode and address mode
+        let byte = self.fetch8(&mut cursor).map_err(bus_fault)?;
+        let opcode =
+            Opcode::try_from(byte).map_err(|byte| VmError::InvalidOpcode { ip: addr, byte })?;
+        let byte = self.fetch8(&mut cursor).map_err(bus_fault)?;
+        let addr_mode =
+            AddrMode::try_from(byte).map_err(|byte| VmError::InvalidAddrMode { ip: addr, byte })?;
+
+        // TODO: Add u8 to format to encode the lenght of the operands (Null = 0, Reg = 1, Mem = Dynamic, Imm = Dynamic)
+
+        // Match the address mode and get the operands
+        let operands = self
+            .fetch_operands(&addr_mode, &mut cursor)
+            .map_err(bus_fault)?;
+
+        let instr = Instruction::new(opcode, addr_mode, operands);
+        Ok((instr, cursor.wrapping_sub(addr)))
     }
 
-    // Attach a device to the CPU
+    // Attach a device to the CPU, allowing every access to it
     pub fn attach(&mut self, box_device: Box<dyn Device>, dev_name: String, start_addr: u64) {
-        self.dev_mapper.map(box_device, dev_name, start_addr);
+        self.attach_with(box_device, dev_name, start_addr, Permissions::ALL);
+    }
+
+    // Attach a device to the CPU, allowing only the given accesses to it
+    pub fn attach_with(
+        &mut self,
+        box_device: Box<dyn Device>,
+        dev_name: String,
+        start_addr: u64,
+        permissions: Permissions,
+    ) {
+        self.dev_mapper
+            .map(box_device, dev_name, start_addr, permissions);
+    }
+
+    // Returns the number of cycles executed, every instruction is one cycle
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Tick the devices every interval cycles, with the number of cycles since the last tick. 0 stops ticking them
+    pub fn set_tick_interval(&mut self, interval: u64) {
+        self.tick_interval = interval;
+    }
+
+    // Put every device back in its power-on state
+    pub fn reset_devices(&mut self) {
+        self.dev_mapper.reset_devices();
+    }
+
+    // Stop runs before executing the instruction at an address
+    pub fn add_breakpoint(&mut self, addr: u64) {
+        self.breakpoints.insert(addr, None);
+    }
+
+    // Stop runs before executing the instruction at an address if the condition holds
+    pub fn add_conditional_breakpoint(&mut self, addr: u64, condition: BreakCondition) {
+        self.breakpoints.insert(addr, Some(condition));
+    }
+
+    // Remove the breakpoint at an address
+    pub fn remove_breakpoint(&mut self, addr: u64) {
+        self.breakpoints.remove(&addr);
+    }
+
+    // Stop runs after an instruction accesses a range of len bytes at an address
+    pub fn add_watchpoint(&mut self, start: u64, len: u64, kind: WatchKind) {
+        self.dev_mapper
+            .add_watchpoint(Watchpoint::new(start, len, kind));
+    }
+
+    // Remove the watchpoints starting at an address
+    pub fn remove_watchpoint(&mut self, start: u64) {
+        self.dev_mapper.remove_watchpoint(start);
+    }
+
+    // Register the host function SYS calls with a number, replacing the previous one
+    pub fn register_syscall(&mut self, number: u64, handler: SyscallHandler) {
+        self.syscalls.insert(number, handler);
+    }
+
+    // Trace the executed instructions, replacing the previous tracer
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    // Stop tracing, returns the tracer so it can be finished
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    // Save the state of the machine: the registers, whether it runs, the pending interrupts, the cycle counter and the devices
+    pub fn snapshot(&self) -> SaveState {
+        SaveState {
+            regs: self.registers().iter().collect(),
+            running: self.running,
+            interrupts: self.dev_mapper.interrupts().requests(),
+            cycles: self.cycles,
+            devices: self.dev_mapper.save_devices(),
+        }
+    }
+
+    // Restore a saved state, on a machine with the same devices mapped at the same addresses
+    pub fn restore(&mut self, state: &SaveState) -> Result<(), SnapshotError> {
+        self.dev_mapper.load_devices(&state.devices)?;
+        for (reg, value) in state.regs.iter() {
+            self.write_reg(*reg, *value);
+        }
+        self.running = state.running;
+        self.dev_mapper.interrupts().set_requests(state.interrupts);
+        self.cycles = state.cycles;
+        Ok(())
+    }
+
+    // Record the nondeterministic inputs of the run from now on, taking a checkpoint every interval
+    // instructions to execute in reverse from
+    pub fn start_recording(&mut self, interval: u64) {
+        self.replay(ReplayLog::default(), interval);
+    }
+
+    // Replay a recorded run, from a machine in the state the recording started from.
+    // The run goes on recording after the end of the log
+    pub fn replay(&mut self, log: ReplayLog, interval: u64) {
+        self.dev_mapper
+            .start_history(BusHistory::new(log.reads.clone()));
+        self.recorder = Some(Recorder::new(log, interval));
+        self.checkpoint();
+    }
+
+    // Stop recording, returns the log of the run
+    pub fn stop_recording(&mut self) -> Option<ReplayLog> {
+        let recorder = self.recorder.take()?;
+        let history = self.dev_mapper.take_history().unwrap_or_default();
+        Some(recorder.into_log(history.into_reads()))
+    }
+
+    // Returns the instruction of the recorded run the CPU is at and the number of instructions recorded
+    pub fn recording_position(&self) -> Option<(u64, u64)> {
+        self.recorder
+            .as_ref()
+            .map(|recorder| (recorder.step, recorder.recorded))
+    }
+
+    // Go back to the state before the last executed instruction
+    pub fn reverse_step(&mut self) -> Result<StopReason, VmError> {
+        match self.recording_position() {
+            Some((step, _)) if step > 0 => {
+                self.seek(step - 1)?;
+                Ok(StopReason::InstructionLimit)
+            }
+            _ => Ok(StopReason::RecordingStart),
+        }
+    }
+
+    // Go back to the last breakpoint hit before the current instruction, or the start of the recording
+    pub fn reverse_continue(&mut self) -> Result<StopReason, VmError> {
+        let mut end = match self.recording_position() {
+            Some((step, _)) => step,
+            None => return Ok(StopReason::RecordingStart),
+        };
+
+        // Replay the run a checkpoint at a time, going backwards, until a breakpoint is hit in it
+        while end > 0 {
+            let start = self.checkpoint_before(end - 1);
+            self.seek(start)?;
+
+            let mut hit = None;
+            for step in start..end {
+                if let Some(StopReason::Breakpoint { addr }) = self.check_breakpoint() {
+                    hit = Some((step, addr));
+                }
+                self.step()?;
+            }
+
+            if let Some((step, addr)) = hit {
+                self.seek(step)?;
+                return Ok(StopReason::Breakpoint { addr });
+            }
+            end = start;
+        }
+
+        self.seek(0)?;
+        Ok(StopReason::RecordingStart)
+    }
+
+    // Read the value of a register
+    pub fn reg(&self, reg: Register) -> u64 {
+        self.read_reg(reg)
+    }
+
+    // Overwrite the value of a register
+    pub fn set_reg(&mut self, reg: Register, value: u64) {
+        self.write_reg(reg, value);
+    }
+
+    // Take a snapshot of all the registers
+    pub fn registers(&self) -> RegisterSnapshot {
+        let values = self
+            .regs_names
+            .iter()
+            .map(|reg| (*reg, self.read_reg(*reg)))
+            .collect();
+        RegisterSnapshot::new(values)
+    }
+
+    // Walk the frames saved by CAL and traps, starting with the current instruction
+    pub fn backtrace(&self) -> Vec<Frame> {
+        let mut frames = vec![Frame {
+            addr: self.read_reg(Register::InstructionPointer),
+            frame_pointer: self.read_reg(Register::FramePointer),
+        }];
+
+        // The frame pointer is 0 outside of any call
+        let mut fp = frames[0].frame_pointer;
+        while fp != 0 && frames.len() < MAX_BACKTRACE {
+            let saved = (
+                self.peek64(fp.wrapping_add(FRAME_SAVED_IP)),
+                self.peek64(fp.wrapping_add(FRAME_SAVED_FP)),
+            );
+            let (addr, caller_fp) = match saved {
+                (Ok(addr), Ok(caller_fp)) => (addr, caller_fp),
+                _ => break,
+            };
+            frames.push(Frame {
+                addr,
+                frame_pointer: caller_fp,
+            });
+            fp = caller_fp;
+        }
+
+        frames
+    }
+
+    // Read len bytes of memory through the device mapper
+    pub fn read_mem(&mut self, addr: u64, len: usize) -> Result<Vec<u8>, BusFault> {
+        (0..len as u64)
+            .map(|i| self.dev_mapper.peek(addr.wrapping_add(i)))
+            .collect()
+    }
+
+    // Write bytes to memory through the device mapper
+    pub fn write_mem(&mut self, addr: u64, data: &[u8]) -> Result<(), BusFault> {
+        for (i, byte) in data.iter().enumerate() {
+            self.dev_mapper.poke(addr.wrapping_add(i as u64), *byte)?;
+        }
+        Ok(())
     }
 }
 
@@ -95,133 +518,453 @@ impl Cpu {
         *self.regs_addr_map.get(&reg).unwrap()
     }
 
-    // Index an register
-    fn index_reg(&mut self, index: u8) -> Register {
-        self.regs_names[index as usize]
+    // Index an register by its code
+    fn index_reg(&mut self, index: u8) -> Result<Register, Fault> {
+        Register::try_from(index).map_err(Fault::InvalidRegister)
     }
 
-    // Fetch 8 bits of data from the instruction pointer
-    fn fetch8(&mut self) -> u8 {
-        let ip = self.read_reg(Register::InstructionPointer);
-        let data = self.dev_mapper.read(ip);
-        self.write_reg(Register::InstructionPointer, ip + 1);
-        data as u8
+    // Read a 64-bit value without triggering watchpoints
+    fn peek64(&self, addr: u64) -> Result<u64, BusFault> {
+        self.dev_mapper.peek64(addr)
     }
 
-    // Fetch 64 bits of data from the instruction pointer
-    fn fetch64(&mut self) -> u64 {
-        let ip = self.read_reg(Register::InstructionPointer);
-        let mut bytes = [0; 8];
-        for i in 0..8 {
-            bytes[i] = self.dev_mapper.read(ip + i as u64);
-        }
-        let data = u64::from_be_bytes(bytes);
-        self.write_reg(Register::InstructionPointer, ip + 8);
-        data
+    // Fetch 8 bits of data from the cursor and advance it, the memory has to be executable
+    fn fetch8(&self, cursor: &mut u64) -> Result<u8, BusFault> {
+        let data = self.dev_mapper.fetch(*cursor)?;
+        *cursor = cursor.wrapping_add(1);
+        Ok(data)
     }
 
-    // Fetch instruction
-    fn fetch(&mut self) -> Instruction {
-        // Fetch the opcode and address mode
-        let opcode = Opcode::from(self.fetch8());
-        let addr_mode = AddrMode::from(self.fetch8());
-
-        // TODO: Add u8 to format to encode the lenght of the operands (Null = 0, Reg = 1, Mem = Dynamic, Imm = Dynamic)
-
-        // Match the address mode and get the operands
-        let operands = self.fetch_operands(&addr_mode);
+    // Fetch 64 bits of big endian data from the cursor and advance it, the memory has to be executable
+    fn fetch64(&self, cursor: &mut u64) -> Result<u64, BusFault> {
+        let data = self.dev_mapper.fetch64(*cursor)?.swap_bytes();
+        *cursor = cursor.wrapping_add(8);
+        Ok(data)
+    }
 
-        // Return the fetched instruction
-        Instruction::new(opcode, addr_mode, operands)
+    // Fetch instruction
+    fn fetch(&mut self) -> Result<Instruction, VmError> {
+        let ip = self.read_reg(Register::InstructionPointer);
+        let (instr, len) = self.decode(ip)?;
+        self.write_reg(Register::InstructionPointer, ip.wrapping_add(len));
+        Ok(instr)
     }
 
     // Fetch operands
-    fn fetch_operands(&mut self, addr_mode: &AddrMode) -> (Operand, Operand) {
+    fn fetch_operands(
+        &self,
+        addr_mode: &AddrMode,
+        cursor: &mut u64,
+    ) -> Result<(Operand, Operand), BusFault> {
         use AddrMode::*;
         use Operand::*;
-        match addr_mode {
+        let c = cursor;
+        Ok(match addr_mode {
             AddrMode::Null => (Operand::Null, Operand::Null),
-            RegToReg => (Reg(self.fetch8()), Reg(self.fetch8())),
-            RegToMem => (Reg(self.fetch8()), Mem(self.fetch64())),
-            ImmToReg => (Imm(self.fetch64()), Reg(self.fetch8())),
-            ImmToMem => (Imm(self.fetch64()), Mem(self.fetch64())),
-            MemToReg => (Mem(self.fetch64()), Reg(self.fetch8())),
-            MemToMem => (Mem(self.fetch64()), Mem(self.fetch64())),
-            Literal => (Imm(self.fetch64()), Operand::Null),
-            Register => (Reg(self.fetch8()), Operand::Null),
-            Memory => (Mem(self.fetch64()), Operand::Null),
-        }
+            RegToReg => (Reg(self.fetch8(c)?), Reg(self.fetch8(c)?)),
+            RegToMem => (Reg(self.fetch8(c)?), Mem(self.fetch64(c)?)),
+            ImmToReg => (Imm(self.fetch64(c)?), Reg(self.fetch8(c)?)),
+            ImmToMem => (Imm(self.fetch64(c)?), Mem(self.fetch64(c)?)),
+            MemToReg => (Mem(self.fetch64(c)?), Reg(self.fetch8(c)?)),
+            MemToMem => (Mem(self.fetch64(c)?), Mem(self.fetch64(c)?)),
+            Literal => (Imm(self.fetch64(c)?), Operand::Null),
+            Register => (Reg(self.fetch8(c)?), Operand::Null),
+            Memory => (Mem(self.fetch64(c)?), Operand::Null),
+            IndToReg => (Ind(self.fetch8(c)?), Reg(self.fetch8(c)?)),
+            RegToInd => (Reg(self.fetch8(c)?), Ind(self.fetch8(c)?)),
+            ImmToInd => (Imm(self.fetch64(c)?), Ind(self.fetch8(c)?)),
+            DispToReg => (self.fetch_disp(c)?, Reg(self.fetch8(c)?)),
+            RegToDisp => (Reg(self.fetch8(c)?), self.fetch_disp(c)?),
+            ImmToDisp => (Imm(self.fetch64(c)?), self.fetch_disp(c)?),
+            IdxToReg => (self.fetch_idx(c)?, Reg(self.fetch8(c)?)),
+            RegToIdx => (Reg(self.fetch8(c)?), self.fetch_idx(c)?),
+            ImmToIdx => (Imm(self.fetch64(c)?), self.fetch_idx(c)?),
+            Relative => (Rel(self.fetch64(c)? as i64), Operand::Null),
+            RelToReg => (self.fetch_ip_disp(c)?, Reg(self.fetch8(c)?)),
+            RegToRel => (Reg(self.fetch8(c)?), self.fetch_ip_disp(c)?),
+            ImmToRel => (Imm(self.fetch64(c)?), self.fetch_ip_disp(c)?),
+        })
+    }
+
+    // Fetch a displacement from the instruction pointer
+    fn fetch_ip_disp(&self, cursor: &mut u64) -> Result<Operand, BusFault> {
+        let disp = self.fetch64(cursor)? as i64;
+        Ok(Operand::Disp(Register::InstructionPointer as u8, disp))
+    }
+
+    // Fetch a base register and a signed displacement
+    fn fetch_disp(&self, cursor: &mut u64) -> Result<Operand, BusFault> {
+        let base = self.fetch8(cursor)?;
+        let disp = self.fetch64(cursor)? as i64;
+        Ok(Operand::Disp(base, disp))
+    }
+
+    // Fetch a base register, an index register and a scale
+    fn fetch_idx(&self, cursor: &mut u64) -> Result<Operand, BusFault> {
+        let base = self.fetch8(cursor)?;
+        let index = self.fetch8(cursor)?;
+        let scale = self.fetch8(cursor)?;
+        Ok(Operand::Idx(base, index, scale))
+    }
+
+    // Resolve register based memory operands to the memory address they point at,
+    // and relative addresses to absolute ones. The instruction pointer already points at the next instruction
+    fn resolve(&mut self, operand: Operand) -> Result<Operand, Fault> {
+        use Operand::*;
+        Ok(match operand {
+            Rel(rel) => {
+                let ip = self.read_reg(Register::InstructionPointer);
+                Imm(ip.wrapping_add_signed(rel))
+            }
+            Ind(reg) => {
+                let reg = self.index_reg(reg)?;
+                Mem(self.read_reg(reg))
+            }
+            Disp(base, disp) => {
+                let base = self.index_reg(base)?;
+                Mem(self.read_reg(base).wrapping_add_signed(disp))
+            }
+            Idx(base, index, scale) => {
+                if !matches!(scale, 1 | 2 | 4 | 8) {
+                    return Err(Fault::InvalidOperands);
+                }
+                let base = self.index_reg(base)?;
+                let index = self.index_reg(index)?;
+                let offset = self.read_reg(index).wrapping_mul(scale as u64);
+                Mem(self.read_reg(base).wrapping_add(offset))
+            }
+            operand => operand,
+        })
     }
 
     // Push the state of the CPU to the stack
-    fn push_state(&mut self) {
+    fn push_state(&mut self) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
 
         // Push the registers
-        self.psh((Reg(InstructionPointer as u8), Null));
-        self.psh((Reg(Reg0 as u8), Null));
-        self.psh((Reg(Reg1 as u8), Null));
-        self.psh((Reg(Reg2 as u8), Null));
-        self.psh((Reg(Reg3 as u8), Null));
-        self.psh((Reg(Reg4 as u8), Null));
-        self.psh((Reg(Reg5 as u8), Null));
-        self.psh((Reg(Reg6 as u8), Null));
-        self.psh((Reg(Reg7 as u8), Null));
-
-        // Push the stack frame size
-        self.psh((Reg(FrameSize as u8), Null));
+        self.psh((Reg(InstructionPointer as u8), Null))?;
+        self.psh((Reg(Reg0 as u8), Null))?;
+        self.psh((Reg(Reg1 as u8), Null))?;
+        self.psh((Reg(Reg2 as u8), Null))?;
+        self.psh((Reg(Reg3 as u8), Null))?;
+        self.psh((Reg(Reg4 as u8), Null))?;
+        self.psh((Reg(Reg5 as u8), Null))?;
+        self.psh((Reg(Reg6 as u8), Null))?;
+        self.psh((Reg(Reg7 as u8), Null))?;
+
+        // Push the frame pointer and the stack frame size
+        self.psh((Reg(FramePointer as u8), Null))?;
+        self.psh((Reg(FrameSize as u8), Null))?;
         self.write_reg(FrameSize, 0);
 
         // Write the new frame pointer
         let sp = self.read_reg(StackPointer);
         self.write_reg(FramePointer, sp);
+
+        Ok(())
     }
 
     // Pop the state of the CPU from the stack
-    fn pop_state(&mut self) {
+    fn pop_state(&mut self) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
 
-        // Pop the stack frame size
-        self.pop((Reg(FrameSize as u8), Null));
+        // Pop the stack frame size and the frame pointer
+        self.pop((Reg(FrameSize as u8), Null))?;
+        self.pop((Reg(FramePointer as u8), Null))?;
 
         // Pop the registers
-        self.pop((Reg(Reg7 as u8), Null));
-        self.pop((Reg(Reg6 as u8), Null));
-        self.pop((Reg(Reg5 as u8), Null));
-        self.pop((Reg(Reg4 as u8), Null));
-        self.pop((Reg(Reg3 as u8), Null));
-        self.pop((Reg(Reg2 as u8), Null));
-        self.pop((Reg(Reg1 as u8), Null));
-        self.pop((Reg(Reg0 as u8), Null));
-        self.pop((Reg(InstructionPointer as u8), Null));
+        self.pop((Reg(Reg7 as u8), Null))?;
+        self.pop((Reg(Reg6 as u8), Null))?;
+        self.pop((Reg(Reg5 as u8), Null))?;
+        self.pop((Reg(Reg4 as u8), Null))?;
+        self.pop((Reg(Reg3 as u8), Null))?;
+        self.pop((Reg(Reg2 as u8), Null))?;
+        self.pop((Reg(Reg1 as u8), Null))?;
+        self.pop((Reg(Reg0 as u8), Null))?;
+        self.pop((Reg(InstructionPointer as u8), Null))?;
+
+        Ok(())
+    }
+
+    // Take a checkpoint of the recorded run if one is due
+    fn checkpoint(&mut self) {
+        let (step, due, replaying) = match self.recorder.as_ref() {
+            Some(recorder) => (
+                recorder.step,
+                recorder.checkpoint_due(),
+                recorder.replaying(),
+            ),
+            None => return,
+        };
+        let history = match self.dev_mapper.history_mut() {
+            Some(history) => history,
+            None => return,
+        };
+
+        // The bus replays the reads the recorded instructions made
+        history.set_replaying(replaying);
+        if !due {
+            return;
+        }
+        let (reads, journal) = (history.cursor(), history.journal_len());
+        let checkpoint = Checkpoint {
+            step,
+            regs: self.registers(),
+            running: self.running,
+            cycles: self.cycles,
+            reads,
+            journal,
+        };
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.checkpoints.push(checkpoint);
+        }
+    }
+
+    // Returns the instruction of the latest checkpoint at or before an instruction
+    fn checkpoint_before(&self, step: u64) -> u64 {
+        self.recorder
+            .as_ref()
+            .and_then(|recorder| recorder.checkpoint_before(step))
+            .map_or(0, |checkpoint| checkpoint.step)
+    }
+
+    // Move to an instruction of the recorded run, by restoring the latest checkpoint before it
+    // and replaying the run from there. Breakpoints, watchpoints and the tracer are ignored
+    fn seek(&mut self, step: u64) -> Result<(), VmError> {
+        let checkpoint = match self
+            .recorder
+            .as_ref()
+            .and_then(|recorder| recorder.checkpoint_before(step))
+        {
+            Some(checkpoint) => checkpoint.clone(),
+            None => return Ok(()),
+        };
+
+        self.dev_mapper.rewind(checkpoint.journal);
+        if let Some(history) = self.dev_mapper.history_mut() {
+            history.seek(checkpoint.reads);
+        }
+        for (reg, value) in checkpoint.regs.iter() {
+            self.write_reg(reg, value);
+        }
+        self.running = checkpoint.running;
+        self.cycles = checkpoint.cycles;
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.step = checkpoint.step;
+        }
+
+        let tracer = self.tracer.take();
+        let mut result = Ok(());
+        while self.recording_position().is_some_and(|(at, _)| at < step) {
+            if let Err(err) = self.step() {
+                result = Err(err);
+                break;
+            }
+        }
+        self.dev_mapper.take_watch_hit();
+        self.tracer = tracer;
+        result
+    }
+
+    // Run until the CPU returns out of depth frames, counting the calls made on the way.
+    // Only RET leaves a frame, interrupt and trap handlers return with IRET to where they were entered
+    fn run_out_of(&mut self, mut depth: u64) -> Result<StopReason, VmError> {
+        self.run_until(|outcome| match outcome.flow {
+            Flow::Call { .. } => {
+                depth += 1;
+                false
+            }
+            Flow::Return { .. } if outcome.instr.opcode == Opcode::Ret => {
+                depth = depth.saturating_sub(1);
+                depth == 0
+            }
+            _ => false,
+        })
+    }
+
+    // Tick the devices if the interval is over. Devices are not ticked while replaying a run,
+    // like they do not see the writes, since their effects on the CPU are replayed
+    fn tick(&mut self) {
+        let replaying = self
+            .recorder
+            .as_ref()
+            .is_some_and(|recorder| recorder.replaying());
+        if self.tick_interval != 0 && self.cycles.is_multiple_of(self.tick_interval) && !replaying {
+            self.dev_mapper.tick(self.tick_interval);
+        }
+    }
+
+    // Check if a breakpoint stops the run before the next instruction
+    fn check_breakpoint(&mut self) -> Option<StopReason> {
+        let addr = self.read_reg(Register::InstructionPointer);
+
+        // Take the condition out while it runs, so it can borrow the CPU
+        let mut condition = self.breakpoints.remove(&addr)?;
+        let hit = condition
+            .as_mut()
+            .is_none_or(|condition| condition(&mut CpuContext::new(self)));
+        self.breakpoints.insert(addr, condition);
+
+        hit.then_some(StopReason::Breakpoint { addr })
+    }
+
+    // Check if the last instruction triggered a watchpoint
+    fn check_watchpoint(&mut self) -> Option<StopReason> {
+        self.dev_mapper
+            .take_watch_hit()
+            .map(|hit| StopReason::Watchpoint {
+                addr: hit.addr,
+                access: hit.access,
+            })
+    }
 
-        // Reset frame pointer
-        let fs = self.read_reg(FrameSize);
-        self.write_reg(FramePointer, fs + self.read_reg(FramePointer));
+    // Enter the handler of a vector if the guest installed one, returns if it did
+    fn trap(&mut self, vector: u64) -> Result<bool, Fault> {
+        let table = self.read_reg(Register::VectorTable);
+        if table == 0 {
+            return Ok(false);
+        }
+
+        let entry = table.wrapping_add(vector.wrapping_mul(std::mem::size_of::<u64>() as u64));
+        let handler = self.dev_mapper.read64(entry)?;
+        if handler == 0 {
+            return Ok(false);
+        }
+
+        // Save the flags and the state like a call, so the handler can return with iret
+        let flags = self.read_reg(Register::Flags);
+        self.psh((Operand::Imm(flags), Operand::Null))?;
+        self.push_state()?;
+
+        // Handlers start with interrupts disabled
+        self.write_reg(Register::Flags, flags & !flags::INTERRUPT);
+        self.write_reg(Register::InstructionPointer, handler);
+
+        Ok(true)
+    }
+
+    // Enter the handler of a bus fault, which finds the faulting address in R0 and the kind of access in R1.
+    // The registers are saved, so they are restored when the handler returns
+    fn trap_bus_fault(&mut self, fault: BusFault) -> Result<bool, Fault> {
+        let trapped = self.trap(vectors::BUS_FAULT)?;
+        if trapped {
+            self.write_reg(Register::Reg0, fault.addr);
+            self.write_reg(Register::Reg1, fault.kind.code());
+        }
+        Ok(trapped)
+    }
+
+    // Enter the handler of the pending interrupt with the highest priority, if interrupts are enabled.
+    // Returns the vector of the interrupt, an interrupt without a handler is dropped
+    fn interrupt(&mut self) -> Result<Option<u64>, VmError> {
+        // Take the interrupts where the recording took them, instead of the pending ones
+        let irq = match self
+            .recorder
+            .as_ref()
+            .filter(|recorder| recorder.replaying())
+        {
+            Some(recorder) => match recorder.interrupt() {
+                Some(irq) => irq,
+                None => return Ok(None),
+            },
+            None => {
+                if !self.flag(flags::INTERRUPT) {
+                    return Ok(None);
+                }
+                let irq = match self.dev_mapper.interrupts().pending() {
+                    Some(irq) => irq,
+                    None => return Ok(None),
+                };
+                self.dev_mapper.interrupts().acknowledge(irq);
+                if let Some(recorder) = self.recorder.as_mut() {
+                    recorder.record_interrupt(irq);
+                }
+                irq
+            }
+        };
+
+        // Entering the handler faults like an INT at the interrupted instruction
+        let ip = self.read_reg(Register::InstructionPointer);
+        let vector = vectors::IRQ_BASE + irq as u64;
+        let trapped = self
+            .trap(vector)
+            .map_err(|fault| fault.at(ip, Opcode::Int, AddrMode::Literal))?;
+
+        Ok(trapped.then_some(vector))
+    }
+
+    // Execute an instruction located at ip and trace it, returns the vector if it trapped into a handler
+    fn execute(&mut self, ip: u64, instr: Instruction) -> Result<Option<u64>, VmError> {
+        let traced = self
+            .tracer
+            .as_ref()
+            .is_some_and(|tracer| tracer.traces(ip, instr.opcode));
+        if !traced {
+            return self.dispatch(ip, instr);
+        }
+
+        // Compare the registers and collect the writes around the instruction
+        let before = self.registers();
+        self.dev_mapper.record_writes(true);
+        let result = self.dispatch(ip, instr.clone());
+        let writes = self.dev_mapper.take_writes();
+        self.dev_mapper.record_writes(false);
+
+        let regs = self
+            .registers()
+            .iter()
+            .filter(|(reg, value)| {
+                *reg != Register::InstructionPointer && before.get(*reg) != *value
+            })
+            .collect();
+        let record = TraceRecord {
+            addr: ip,
+            instr,
+            regs,
+            writes,
+            fault: result.as_ref().err().cloned(),
+        };
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(&record);
+        }
+
+        result
     }
 
-    // Execute an instruction
-    fn execute(&mut self, instr: Instruction) {
-        let (opcode, _, operands) = instr.unpack();
+    // Run the handler of an instruction located at ip, returns the vector if it trapped into a handler
+    fn dispatch(&mut self, ip: u64, instr: Instruction) -> Result<Option<u64>, VmError> {
+        let (opcode, addr_mode, (operand, operand2)) = instr.unpack();
+
+        // Turn register based memory operands into plain memory operands
+        let operands = match (self.resolve(operand), self.resolve(operand2)) {
+            (Ok(operand), Ok(operand2)) => (operand, operand2),
+            (Err(fault), _) | (_, Err(fault)) => return Err(fault.at(ip, opcode, addr_mode)),
+        };
 
         use Opcode::*;
-        match opcode {
+        let result = match opcode {
             // Misc
-            Nop => {}
+            Nop => Ok(()),
             Hlt => self.hlt(),
 
             // Load and store
-            Mov => self.mov(operands),
-            Lod => self.lod(operands),
-            Str => self.str(operands),
+            Mov | Movb | Movh | Movw => self.mov(operands, opcode.width()),
+            Lod | Lodb | Lodh | Lodw => self.lod(operands, opcode.width(), false),
+            Lodsb | Lodsh | Lodsw => self.lod(operands, opcode.width(), true),
+            Str | Strb | Strh | Strw => self.str(operands, opcode.width()),
 
             // Arithmetic
             Add => self.add(operands),
             Sub => self.sub(operands),
             Mul => self.mul(operands),
             Div => self.div(operands),
+            Cmp => self.cmp(operands),
+            Imul => self.imul(operands),
+            Idiv => self.idiv(operands),
+            Mod => self.modulo(operands),
+            Imod => self.imod(operands),
             Inc => self.inc(operands),
             Dec => self.dec(operands),
 
@@ -229,6 +972,8 @@ impl Cpu {
             And => self.and(operands),
             Or => self.or(operands),
             Xor => self.xor(operands),
+            Tst => self.tst(operands),
+            Sar => self.sar(operands),
             Not => self.not(operands),
             Shl => self.shl(operands),
             Shr => self.shr(operands),
@@ -243,6 +988,10 @@ impl Cpu {
             Jle => self.jle(operands),
             Jnz => self.jnz(operands),
             Jz => self.jz(operands),
+            Jsgt => self.jsgt(operands),
+            Jslt => self.jslt(operands),
+            Jsge => self.jsge(operands),
+            Jsle => self.jsle(operands),
 
             // Stack
             Psh => self.psh(operands),
@@ -252,708 +1001,673 @@ impl Cpu {
             Clr => self.clr(operands),
             Ret => self.ret(operands),
             Cal => self.cal(operands),
+
+            // Interrupts
+            Int => self.int(operands),
+            Iret => self.iret(operands),
+            Cli => self.cli(operands),
+            Sti => self.sti(operands),
+            Sys => self.sys(operands),
+        };
+
+        match result {
+            Ok(()) => Ok(None),
+            Err(fault) => {
+                // Let the guest handle the fault if it installed a handler for it
+                if let Some(vector) = fault.vector() {
+                    let trapped = match fault {
+                        Fault::Bus(bus_fault) => self.trap_bus_fault(bus_fault),
+                        _ => self.trap(vector),
+                    }
+                    .map_err(|fault| fault.at(ip, opcode, addr_mode))?;
+                    if trapped {
+                        return Ok(Some(vector));
+                    }
+                }
+
+                Err(fault.at(ip, opcode, addr_mode))
+            }
         }
     }
 
-    fn hlt(&mut self) {
+    fn hlt(&mut self) -> Result<(), Fault> {
         self.running = false;
+        Ok(())
     }
 
-    // Move
-    fn mov(&mut self, operands: (Operand, Operand)) {
+    // Move, width bytes at a time
+    fn mov(&mut self, operands: (Operand, Operand), width: Width) -> Result<(), Fault> {
         use Operand::*;
         match operands {
             // Imm -> Reg
             (Imm(imm), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                self.write_reg(reg, imm);
+                let reg = self.index_reg(reg)?;
+                self.write_reg(reg, width.extend(imm, false));
             }
             // Imm -> Mem
             (Imm(imm), Mem(mem)) => {
-                self.dev_mapper.write64(mem, imm);
+                self.dev_mapper.write_sized(mem, imm, width.bytes())?;
             }
             // Reg -> Reg
             (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
+                let reg1 = self.index_reg(reg)?;
+                let reg2 = self.index_reg(reg2)?;
                 let data = self.read_reg(reg1);
-                self.write_reg(reg2, data);
+                self.write_reg(reg2, width.extend(data, false));
             }
             // Reg -> Mem
             (Reg(reg), Mem(mem)) => {
-                let reg = self.index_reg(reg);
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
-                self.dev_mapper.write64(mem, data);
+                self.dev_mapper.write_sized(mem, data, width.bytes())?;
             }
             // Mem -> Reg
             (Mem(mem), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.dev_mapper.read64(mem);
+                let reg = self.index_reg(reg)?;
+                let data = self.dev_mapper.read_sized(mem, width.bytes())?;
                 self.write_reg(reg, data);
             }
             // Mem -> Mem
             (Mem(mem), Mem(mem2)) => {
-                let data = self.dev_mapper.read(mem);
-                self.dev_mapper.write(mem2, data);
+                let data = self.dev_mapper.read_sized(mem, width.bytes())?;
+                self.dev_mapper.write_sized(mem2, data, width.bytes())?;
             }
-            _ => panic!("Invalid operands for mov instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
-    // Load
-    fn lod(&mut self, operands: (Operand, Operand)) {
+    // Load, width bytes at a time and sign extended if signed
+    fn lod(
+        &mut self,
+        operands: (Operand, Operand),
+        width: Width,
+        signed: bool,
+    ) -> Result<(), Fault> {
         use Operand::*;
         match operands {
             // Imm -> Reg
             (Imm(imm), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                self.write_reg(reg, imm);
+                let reg = self.index_reg(reg)?;
+                self.write_reg(reg, width.extend(imm, signed));
             }
             // Mem -> Reg
             (Mem(mem), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.dev_mapper.read64(mem);
-                self.write_reg(reg, data);
+                let reg = self.index_reg(reg)?;
+                let data = self.dev_mapper.read_sized(mem, width.bytes())?;
+                self.write_reg(reg, width.extend(data, signed));
             }
-            _ => panic!("Invalid operands for lod instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
-    // Store
-    fn str(&mut self, operands: (Operand, Operand)) {
+    // Store, width bytes at a time
+    fn str(&mut self, operands: (Operand, Operand), width: Width) -> Result<(), Fault> {
         use Operand::*;
         match operands {
             // Imm -> Mem
             (Imm(imm), Mem(mem)) => {
-                self.dev_mapper.write64(mem, imm);
+                self.dev_mapper.write_sized(mem, imm, width.bytes())?;
             }
             // Reg -> Mem
             (Reg(reg), Mem(mem)) => {
-                let reg = self.index_reg(reg);
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
-                self.dev_mapper.write64(mem, data);
+                self.dev_mapper.write_sized(mem, data, width.bytes())?;
             }
             // Mem -> Mem
             (Mem(mem), Mem(mem2)) => {
-                let data = self.dev_mapper.read(mem);
-                self.dev_mapper.write(mem2, data);
+                let data = self.dev_mapper.read_sized(mem, width.bytes())?;
+                self.dev_mapper.write_sized(mem2, data, width.bytes())?;
             }
-            _ => panic!("Invalid operands for str instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
-    // Add
-    fn add(&mut self, operands: (Operand, Operand)) {
+    // Read the two values of an arithmetic instruction, in the order they are combined
+    fn arith_operands(&mut self, operands: (Operand, Operand)) -> Result<(u64, u64), Fault> {
         use Operand::*;
-        use Register::*;
         match operands {
             // Imm -> Reg
             (Imm(imm), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                self.write_reg(Accumulator, data + imm);
+                let reg = self.index_reg(reg)?;
+                Ok((self.read_reg(reg), imm))
             }
             // Reg -> Reg
             (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
-                let data = self.read_reg(reg1);
-                let data2 = self.read_reg(reg2);
-                self.write_reg(Accumulator, data + data2);
+                let reg1 = self.index_reg(reg)?;
+                let reg2 = self.index_reg(reg2)?;
+                Ok((self.read_reg(reg1), self.read_reg(reg2)))
             }
             // Mem -> Reg
             (Mem(mem), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.dev_mapper.read64(mem);
-                let data2 = self.read_reg(reg);
-                self.write_reg(Accumulator, data + data2);
+                let reg = self.index_reg(reg)?;
+                let data = self.dev_mapper.read64(mem)?;
+                Ok((data, self.read_reg(reg)))
             }
-            _ => panic!("Invalid operands for add instruction"),
+            _ => Err(Fault::InvalidOperands),
         }
     }
 
-    // Subtract
-    fn sub(&mut self, operands: (Operand, Operand)) {
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Imm -> Reg
-            (Imm(imm), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                self.write_reg(Accumulator, data - imm);
-            }
-            // Reg -> Reg
-            (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
-                let data = self.read_reg(reg1);
-                let data2 = self.read_reg(reg2);
-                self.write_reg(Accumulator, data - data2);
-            }
-            // Mem -> Reg
-            (Mem(mem), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.dev_mapper.read64(mem);
-                let data2 = self.read_reg(reg);
-                self.write_reg(Accumulator, data - data2);
-            }
-            _ => panic!("Invalid operands for sub instruction"),
+    // Update the flags register from the result of an operation
+    fn set_flags(&mut self, result: u64, carry: bool, overflow: bool) {
+        let mut flags = self.read_reg(Register::Flags) & !flags::ARITHMETIC;
+        if result == 0 {
+            flags |= flags::ZERO;
+        }
+        if carry {
+            flags |= flags::CARRY;
+        }
+        if overflow {
+            flags |= flags::OVERFLOW;
         }
+        if (result as i64) < 0 {
+            flags |= flags::SIGN;
+        }
+        self.write_reg(Register::Flags, flags);
+    }
+
+    // Subtract and set the flags, returning the difference
+    fn sub_flags(&mut self, operands: (Operand, Operand)) -> Result<u64, Fault> {
+        let (data, data2) = self.arith_operands(operands)?;
+        let (result, borrow) = data.overflowing_sub(data2);
+        let (_, overflow) = (data as i64).overflowing_sub(data2 as i64);
+        self.set_flags(result, borrow, overflow);
+        Ok(result)
+    }
+
+    // Add
+    fn add(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.arith_operands(operands)?;
+        let (result, carry) = data.overflowing_add(data2);
+        let (_, overflow) = (data as i64).overflowing_add(data2 as i64);
+        self.set_flags(result, carry, overflow);
+        self.write_reg(Register::Accumulator, result);
+        Ok(())
+    }
+
+    // Subtract
+    fn sub(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let result = self.sub_flags(operands)?;
+        self.write_reg(Register::Accumulator, result);
+        Ok(())
     }
 
     // Multiply
-    fn mul(&mut self, operands: (Operand, Operand)) {
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Imm -> Reg
-            (Imm(imm), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                self.write_reg(Accumulator, data * imm);
-            }
-            // Reg -> Reg
-            (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
-                let data = self.read_reg(reg1);
-                let data2 = self.read_reg(reg2);
-                self.write_reg(Accumulator, data * data2);
-            }
-            // Mem -> Reg
-            (Mem(mem), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.dev_mapper.read64(mem);
-                let data2 = self.read_reg(reg);
-                self.write_reg(Accumulator, data * data2);
-            }
-            _ => panic!("Invalid operands for mul instruction"),
-        }
+    fn mul(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.arith_operands(operands)?;
+        let (result, carry) = data.overflowing_mul(data2);
+        let (_, overflow) = (data as i64).overflowing_mul(data2 as i64);
+        self.set_flags(result, carry, overflow);
+        self.write_reg(Register::Accumulator, result);
+        Ok(())
     }
 
     // Divide
-    fn div(&mut self, operands: (Operand, Operand)) {
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Imm -> Reg
-            (Imm(imm), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                self.write_reg(Accumulator, data / imm);
-            }
-            // Reg -> Reg
-            (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
-                let data = self.read_reg(reg1);
-                let data2 = self.read_reg(reg2);
-                self.write_reg(Accumulator, data / data2);
-            }
-            // Mem -> Reg
-            (Mem(mem), Reg(reg)) => {
-                let reg = self.index_reg(reg);
-                let data = self.dev_mapper.read64(mem);
-                let data2 = self.read_reg(reg);
-                self.write_reg(Accumulator, data / data2);
-            }
-            _ => panic!("Invalid operands for div instruction"),
+    fn div(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.arith_operands(operands)?;
+        let quotient = data.checked_div(data2).ok_or(Fault::DivideByZero)?;
+        self.write_reg(Register::Accumulator, quotient);
+        Ok(())
+    }
+
+    // Signed multiply
+    fn imul(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.arith_operands(operands)?;
+        let (result, overflow) = (data as i64).overflowing_mul(data2 as i64);
+        self.set_flags(result as u64, overflow, overflow);
+        self.write_reg(Register::Accumulator, result as u64);
+        Ok(())
+    }
+
+    // Signed divide, dividing the smallest value by -1 wraps around
+    fn idiv(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.arith_operands(operands)?;
+        if data2 == 0 {
+            return Err(Fault::DivideByZero);
+        }
+        let quotient = (data as i64).wrapping_div(data2 as i64);
+        self.write_reg(Register::Accumulator, quotient as u64);
+        Ok(())
+    }
+
+    // Remainder
+    fn modulo(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.arith_operands(operands)?;
+        let remainder = data.checked_rem(data2).ok_or(Fault::DivideByZero)?;
+        self.write_reg(Register::Accumulator, remainder);
+        Ok(())
+    }
+
+    // Signed remainder, takes the sign of the dividend
+    fn imod(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.arith_operands(operands)?;
+        if data2 == 0 {
+            return Err(Fault::DivideByZero);
         }
+        let remainder = (data as i64).wrapping_rem(data2 as i64);
+        self.write_reg(Register::Accumulator, remainder as u64);
+        Ok(())
+    }
+
+    // Compare, sets the flags like subtract without storing the result
+    fn cmp(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.sub_flags(operands)?;
+        Ok(())
     }
 
     // Increment
-    fn inc(&mut self, operands: (Operand, Operand)) {
+    fn inc(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Reg
             (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
-                self.write_reg(Accumulator, data + 1);
+                self.write_reg(Accumulator, data.wrapping_add(1));
             }
             // Mem
             (Mem(mem), Null) => {
-                let data = self.dev_mapper.read(mem);
-                self.dev_mapper.write(mem, data + 1);
+                let data = self.dev_mapper.read(mem)?;
+                self.dev_mapper.write(mem, data.wrapping_add(1))?;
             }
-            _ => panic!("Invalid operands for inc instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
     // Decrement
-    fn dec(&mut self, operands: (Operand, Operand)) {
+    fn dec(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Reg
             (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
-                self.write_reg(Accumulator, data - 1);
+                self.write_reg(Accumulator, data.wrapping_sub(1));
             }
             // Mem
             (Mem(mem), Null) => {
-                let data = self.dev_mapper.read(mem);
-                self.dev_mapper.write(mem, data - 1);
+                let data = self.dev_mapper.read(mem)?;
+                self.dev_mapper.write(mem, data.wrapping_sub(1))?;
             }
-            _ => panic!("Invalid operands for dec instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
     // Bitwise AND
-    fn and(&mut self, operands: (Operand, Operand)) {
+    fn and(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Reg -> Imm
             (Reg(reg), Imm(imm)) => {
-                let reg = self.index_reg(reg);
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
                 self.write_reg(Accumulator, data & imm);
             }
             // Reg -> Reg
             (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
+                let reg1 = self.index_reg(reg)?;
+                let reg2 = self.index_reg(reg2)?;
                 let data = self.read_reg(reg1);
                 let data2 = self.read_reg(reg2);
                 self.write_reg(Accumulator, data & data2);
             }
-            _ => panic!("Invalid operands for and instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
     // Bitwise OR
-    fn or(&mut self, operands: (Operand, Operand)) {
+    fn or(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Reg -> Imm
             (Reg(reg), Imm(imm)) => {
-                let reg = self.index_reg(reg);
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
                 self.write_reg(Accumulator, data | imm);
             }
             // Reg -> Reg
             (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
+                let reg1 = self.index_reg(reg)?;
+                let reg2 = self.index_reg(reg2)?;
                 let data = self.read_reg(reg1);
                 let data2 = self.read_reg(reg2);
                 self.write_reg(Accumulator, data | data2);
             }
-            _ => panic!("Invalid operands for or instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
     // Bitwise XOR
-    fn xor(&mut self, operands: (Operand, Operand)) {
+    fn xor(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Reg -> Imm
             (Reg(reg), Imm(imm)) => {
-                let reg = self.index_reg(reg);
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
                 self.write_reg(Accumulator, data ^ imm);
             }
             // Reg -> Reg
             (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
+                let reg1 = self.index_reg(reg)?;
+                let reg2 = self.index_reg(reg2)?;
                 let data = self.read_reg(reg1);
                 let data2 = self.read_reg(reg2);
                 self.write_reg(Accumulator, data ^ data2);
             }
-            _ => panic!("Invalid operands for xor instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
-    }
 
-    // Bitwise NOT
-    fn not(&mut self, operands: (Operand, Operand)) {
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg
-            (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                self.write_reg(Accumulator, !data);
-            }
-            // Mem
-            (Mem(mem), Null) => {
-                let data = self.dev_mapper.read64(mem);
-                self.write_reg(Accumulator, !data);
-            }
-            _ => panic!("Invalid operands for not instruction"),
-        }
+        Ok(())
     }
 
-    // Bitwise Shift Left
-    fn shl(&mut self, operands: (Operand, Operand)) {
+    // Read the two values of a bitwise instruction
+    fn bitwise_operands(&mut self, operands: (Operand, Operand)) -> Result<(u64, u64), Fault> {
         use Operand::*;
-        use Register::*;
         match operands {
             // Reg -> Imm
             (Reg(reg), Imm(imm)) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                self.write_reg(Accumulator, data << imm);
+                let reg = self.index_reg(reg)?;
+                Ok((self.read_reg(reg), imm))
             }
             // Reg -> Reg
             (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
-                let data = self.read_reg(reg1);
-                let data2 = self.read_reg(reg2);
-                self.write_reg(Accumulator, data << data2);
+                let reg1 = self.index_reg(reg)?;
+                let reg2 = self.index_reg(reg2)?;
+                Ok((self.read_reg(reg1), self.read_reg(reg2)))
             }
-            _ => panic!("Invalid operands for shl instruction"),
+            _ => Err(Fault::InvalidOperands),
         }
     }
 
-    // Bitwise Shift Right
-    fn shr(&mut self, operands: (Operand, Operand)) {
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg -> Imm
-            (Reg(reg), Imm(imm)) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                self.write_reg(Accumulator, data >> imm);
-            }
-            // Reg -> Reg
-            (Reg(reg), Reg(reg2)) => {
-                let reg1 = self.index_reg(reg);
-                let reg2 = self.index_reg(reg2);
-                let data = self.read_reg(reg1);
-                let data2 = self.read_reg(reg2);
-                self.write_reg(Accumulator, data >> data2);
-            }
-            _ => panic!("Invalid operands for shr instruction"),
-        }
+    // Test, sets the flags like bitwise AND without storing the result
+    fn tst(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.bitwise_operands(operands)?;
+        self.set_flags(data & data2, false, false);
+        Ok(())
     }
 
-    // Jump
-    fn jmp(&mut self, operands: (Operand, Operand)) {
+    // Bitwise NOT
+    fn not(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Reg
             (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
-                self.write_reg(InstructionPointer, data);
+                self.write_reg(Accumulator, !data);
             }
-            // Imm
-            (Imm(imm), Null) => {
-                self.write_reg(InstructionPointer, imm);
+            // Mem
+            (Mem(mem), Null) => {
+                let data = self.dev_mapper.read64(mem)?;
+                self.write_reg(Accumulator, !data);
             }
-            _ => panic!("Invalid operands for jmp instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
-    // Jump if equal
-    fn jeq(&mut self, operands: (Operand, Operand)) {
-        let acc = self.read_reg(Register::Accumulator);
+    // Bitwise Shift Left, shifting by 64 or more bits gives 0
+    fn shl(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.bitwise_operands(operands)?;
+        let result = u32::try_from(data2)
+            .ok()
+            .and_then(|shift| data.checked_shl(shift))
+            .unwrap_or(0);
+        self.write_reg(Register::Accumulator, result);
+        Ok(())
+    }
 
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg
-            (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                if acc == data {
-                    self.write_reg(InstructionPointer, data);
-                }
-            }
-            // Imm
-            (Imm(imm), Null) => {
-                if acc == imm {
-                    self.write_reg(InstructionPointer, imm);
-                }
-            }
-            _ => panic!("Invalid operands for jeq instruction"),
-        }
+    // Bitwise Shift Right, shifting by 64 or more bits gives 0
+    fn shr(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.bitwise_operands(operands)?;
+        let result = u32::try_from(data2)
+            .ok()
+            .and_then(|shift| data.checked_shr(shift))
+            .unwrap_or(0);
+        self.write_reg(Register::Accumulator, result);
+        Ok(())
     }
 
-    // Jump if not equal
-    fn jne(&mut self, operands: (Operand, Operand)) {
-        let acc = self.read_reg(Register::Accumulator);
+    // Arithmetic Shift Right, keeps the sign and shifting by 64 or more bits fills with it
+    fn sar(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let (data, data2) = self.bitwise_operands(operands)?;
+        let shift = data2.min(63) as u32;
+        let result = (data as i64) >> shift;
+        self.write_reg(Register::Accumulator, result as u64);
+        Ok(())
+    }
 
+    // Jump to the target operand if the condition holds
+    fn jump_if(&mut self, condition: bool, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
-        match operands {
+        let target = match operands {
             // Reg
             (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                if acc != data {
-                    self.write_reg(InstructionPointer, data);
-                }
+                let reg = self.index_reg(reg)?;
+                self.read_reg(reg)
             }
             // Imm
-            (Imm(imm), Null) => {
-                if acc != imm {
-                    self.write_reg(InstructionPointer, imm);
-                }
-            }
-            _ => panic!("Invalid operands for jne instruction"),
+            (Imm(imm), Null) => imm,
+            _ => return Err(Fault::InvalidOperands),
+        };
+
+        if condition {
+            self.write_reg(InstructionPointer, target);
         }
+
+        Ok(())
     }
 
-    // Jump if greater than
-    fn jgt(&mut self, operands: (Operand, Operand)) {
-        let acc = self.read_reg(Register::Accumulator);
+    // Check if a flag is set
+    fn flag(&self, flag: u64) -> bool {
+        self.read_reg(Register::Flags) & flag != 0
+    }
 
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg
-            (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                if acc > data {
-                    self.write_reg(InstructionPointer, data);
-                }
-            }
-            // Imm
-            (Imm(imm), Null) => {
-                if acc > imm {
-                    self.write_reg(InstructionPointer, imm);
-                }
-            }
-            _ => panic!("Invalid operands for jgt instruction"),
-        }
+    // Jump
+    fn jmp(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(true, operands)
     }
 
-    // Jump if less than
-    fn jlt(&mut self, operands: (Operand, Operand)) {
-        let acc = self.read_reg(Register::Accumulator);
+    // Jump if equal
+    fn jeq(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(self.flag(flags::ZERO), operands)
+    }
 
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg
-            (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                if acc < data {
-                    self.write_reg(InstructionPointer, data);
-                }
-            }
-            // Imm
-            (Imm(imm), Null) => {
-                if acc < imm {
-                    self.write_reg(InstructionPointer, imm);
-                }
-            }
-            _ => panic!("Invalid operands for jlt instruction"),
-        }
+    // Jump if not equal
+    fn jne(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(!self.flag(flags::ZERO), operands)
     }
 
-    // Jump if greater than or equal to
-    fn jge(&mut self, operands: (Operand, Operand)) {
-        let acc = self.read_reg(Register::Accumulator);
+    // Jump if greater than
+    fn jgt(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let condition = !self.flag(flags::CARRY) && !self.flag(flags::ZERO);
+        self.jump_if(condition, operands)
+    }
 
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg
-            (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                if acc >= data {
-                    self.write_reg(InstructionPointer, data);
-                }
-            }
-            // Imm
-            (Imm(imm), Null) => {
-                if acc >= imm {
-                    self.write_reg(InstructionPointer, imm);
-                }
-            }
-            _ => panic!("Invalid operands for jge instruction"),
-        }
+    // Jump if less than
+    fn jlt(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(self.flag(flags::CARRY), operands)
     }
 
-    // Jump if less than or equal to
-    fn jle(&mut self, operands: (Operand, Operand)) {
-        let acc = self.read_reg(Register::Accumulator);
+    // Jump if greater than or equal to
+    fn jge(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(!self.flag(flags::CARRY), operands)
+    }
 
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg
-            (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                if acc <= data {
-                    self.write_reg(InstructionPointer, data);
-                }
-            }
-            // Imm
-            (Imm(imm), Null) => {
-                if acc <= imm {
-                    self.write_reg(InstructionPointer, imm);
-                }
-            }
-            _ => panic!("Invalid operands for jle instruction"),
-        }
+    // Jump if less than or equal to
+    fn jle(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let condition = self.flag(flags::CARRY) || self.flag(flags::ZERO);
+        self.jump_if(condition, operands)
     }
 
     // Jump not zero
-    fn jnz(&mut self, operands: (Operand, Operand)) {
-        let acc = self.read_reg(Register::Accumulator);
-
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg
-            (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                if acc != 0 {
-                    self.write_reg(InstructionPointer, data);
-                }
-            }
-            // Imm
-            (Imm(imm), Null) => {
-                if acc != 0 {
-                    self.write_reg(InstructionPointer, imm);
-                }
-            }
-            _ => panic!("Invalid operands for jnz instruction"),
-        }
+    fn jnz(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(!self.flag(flags::ZERO), operands)
     }
 
     // Jump zero
-    fn jz(&mut self, operands: (Operand, Operand)) {
-        let acc = self.read_reg(Register::Accumulator);
+    fn jz(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(self.flag(flags::ZERO), operands)
+    }
 
-        use Operand::*;
-        use Register::*;
-        match operands {
-            // Reg
-            (Reg(reg), Null) => {
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                if acc == 0 {
-                    self.write_reg(InstructionPointer, data);
-                }
-            }
-            // Imm
-            (Imm(imm), Null) => {
-                if acc == 0 {
-                    self.write_reg(InstructionPointer, imm);
-                }
-            }
-            _ => panic!("Invalid operands for jz instruction"),
-        }
+    // Check if the sign flag differs from the overflow flag, meaning a signed less than
+    fn signed_less(&self) -> bool {
+        self.flag(flags::SIGN) != self.flag(flags::OVERFLOW)
+    }
+
+    // Jump if signed greater than
+    fn jsgt(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let condition = !self.flag(flags::ZERO) && !self.signed_less();
+        self.jump_if(condition, operands)
+    }
+
+    // Jump if signed less than
+    fn jslt(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(self.signed_less(), operands)
+    }
+
+    // Jump if signed greater than or equal to
+    fn jsge(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        self.jump_if(!self.signed_less(), operands)
+    }
+
+    // Jump if signed less than or equal to
+    fn jsle(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        let condition = self.flag(flags::ZERO) || self.signed_less();
+        self.jump_if(condition, operands)
     }
 
     // Push to stack
-    fn psh(&mut self, operands: (Operand, Operand)) {
+    fn psh(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
-        match operands {
+        let data = match operands {
             // Imm -> Stack
-            (Imm(imm), Null) => {
-                let sp = self.read_reg(StackPointer);
-                self.dev_mapper.write64(sp, imm);
-                self.write_reg(StackPointer, sp - std::mem::size_of::<u64>() as u64);
-                self.write_reg(
-                    FrameSize,
-                    self.read_reg(FrameSize) + std::mem::size_of::<u64>() as u64,
-                );
-            }
+            (Imm(imm), Null) => imm,
             // Reg -> Stack
             (Reg(reg), _) => {
-                let sp = self.read_reg(StackPointer);
-                let reg = self.index_reg(reg);
-                let data = self.read_reg(reg);
-                self.dev_mapper.write64(sp, data);
-                self.write_reg(StackPointer, sp - std::mem::size_of::<u64>() as u64);
-                self.write_reg(
-                    FrameSize,
-                    self.read_reg(FrameSize) + std::mem::size_of::<u64>() as u64,
-                );
+                let reg = self.index_reg(reg)?;
+                self.read_reg(reg)
             }
-            _ => panic!("Invalid operands for psh instruction"),
-        }
+            _ => return Err(Fault::InvalidOperands),
+        };
+
+        let sp = self.read_reg(StackPointer);
+        let new_sp = sp
+            .checked_sub(std::mem::size_of::<u64>() as u64)
+            .ok_or(Fault::StackOverflow)?;
+        self.dev_mapper.write64(sp, data)?;
+        self.write_reg(StackPointer, new_sp);
+        self.write_reg(
+            FrameSize,
+            self.read_reg(FrameSize)
+                .wrapping_add(std::mem::size_of::<u64>() as u64),
+        );
+
+        Ok(())
     }
 
-    // Pop from stack
-    fn pop(&mut self, operands: (Operand, Operand)) {
+    // Pop from stack, the stack pointer points at the free slot below the top of the stack
+    fn pop(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
+        let sp = self.read_reg(StackPointer);
+        let top = sp
+            .checked_add(std::mem::size_of::<u64>() as u64)
+            .ok_or(Fault::StackUnderflow)?;
+
         match operands {
             // Stack -> Reg
             (Reg(reg), Null) => {
-                let sp = self.read_reg(StackPointer);
-                let reg = self.index_reg(reg);
-                let data = self.dev_mapper.read64(sp);
+                let reg = self.index_reg(reg)?;
+                let data = self.dev_mapper.read64(top)?;
                 self.write_reg(reg, data);
-                self.write_reg(StackPointer, sp + std::mem::size_of::<u64>() as u64);
-                self.write_reg(
-                    FrameSize,
-                    self.read_reg(FrameSize) - std::mem::size_of::<u64>() as u64,
-                );
             }
             // Null
-            (Null, Null) => {
-                let sp = self.read_reg(StackPointer);
-                self.write_reg(StackPointer, sp + std::mem::size_of::<u64>() as u64);
-                self.write_reg(
-                    FrameSize,
-                    self.read_reg(FrameSize) - std::mem::size_of::<u64>() as u64,
-                );
-            }
-            _ => panic!("Invalid operands for pop instruction"),
+            (Null, Null) => {}
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        self.write_reg(StackPointer, top);
+        self.write_reg(
+            FrameSize,
+            self.read_reg(FrameSize)
+                .wrapping_sub(std::mem::size_of::<u64>() as u64),
+        );
+
+        Ok(())
     }
 
     // Duplicate top of stack
-    fn dup(&mut self, operands: (Operand, Operand)) {
+    fn dup(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Stack -> Stack
             (Null, Null) => {
                 let sp = self.read_reg(StackPointer);
-                let data = self.dev_mapper.read(sp);
-                self.dev_mapper
-                    .write(sp - std::mem::size_of::<u64>() as u64, data);
-                self.write_reg(StackPointer, sp - std::mem::size_of::<u64>() as u64);
+                let top = sp
+                    .checked_add(std::mem::size_of::<u64>() as u64)
+                    .ok_or(Fault::StackUnderflow)?;
+                let data = self.dev_mapper.read64(top)?;
+                self.psh((Imm(data), Null))?;
             }
-            _ => panic!("Invalid operands for dup instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
     // Swap top two elements of stack
-    fn swp(&mut self, operands: (Operand, Operand)) {
+    fn swp(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Stack -> Stack
             (Null, Null) => {
                 let sp = self.read_reg(StackPointer);
-                let data1 = self.dev_mapper.read(sp);
-                let data2 = self.dev_mapper.read(sp + std::mem::size_of::<u64>() as u64);
-                self.dev_mapper.write(sp, data2);
-                self.dev_mapper
-                    .write(sp + std::mem::size_of::<u64>() as u64, data1);
-            }
-            _ => panic!("Invalid operands for swp instruction"),
+                let top = sp
+                    .checked_add(std::mem::size_of::<u64>() as u64)
+                    .ok_or(Fault::StackUnderflow)?;
+                let next = top
+                    .checked_add(std::mem::size_of::<u64>() as u64)
+                    .ok_or(Fault::StackUnderflow)?;
+                let data1 = self.dev_mapper.read64(top)?;
+                let data2 = self.dev_mapper.read64(next)?;
+                self.dev_mapper.write64(top, data2)?;
+                self.dev_mapper.write64(next, data1)?;
+            }
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
     // Clear the whole stack
-    fn clr(&mut self, operands: (Operand, Operand)) {
+    fn clr(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
@@ -962,40 +1676,160 @@ impl Cpu {
                 self.write_reg(StackPointer, self.read_reg(FramePointer));
                 self.write_reg(FrameSize, 0);
             }
-            _ => panic!("Invalid operands for clr instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
     // Return from subroutine
-    fn ret(&mut self, operands: (Operand, Operand)) {
+    fn ret(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         match operands {
             // Stack -> Stack
             (Null, Null) => {
-                self.pop_state();
+                self.pop_state()?;
             }
-            _ => panic!("Invalid operands for ret instruction"),
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
     }
 
     // Call a subroutine
-    fn cal(&mut self, operands: (Operand, Operand)) {
+    fn cal(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
         use Operand::*;
         use Register::*;
         match operands {
             // Imm -> Stack
             (Imm(imm), Null) => {
-                self.push_state();
+                self.push_state()?;
                 self.write_reg(InstructionPointer, imm);
             }
             // Reg -> Stack
             (Reg(reg), _) => {
-                self.push_state();
-                let reg = self.index_reg(reg);
+                self.push_state()?;
+                let reg = self.index_reg(reg)?;
                 let data = self.read_reg(reg);
                 self.write_reg(InstructionPointer, data);
             }
-            _ => panic!("Invalid operands for cal instruction"),
+            _ => return Err(Fault::InvalidOperands),
+        }
+
+        Ok(())
+    }
+
+    // Software interrupt, enters the handler of a vector
+    fn int(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        use Operand::*;
+        match operands {
+            // Imm
+            (Imm(vector), Null) => {
+                if !self.trap(vector)? {
+                    return Err(Fault::UnhandledInterrupt(vector));
+                }
+            }
+            _ => return Err(Fault::InvalidOperands),
         }
+
+        Ok(())
+    }
+
+    // Return from an interrupt or trap handler, restoring the state and the flags
+    fn iret(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        use Operand::*;
+        use Register::*;
+        match operands {
+            // Stack -> Stack
+            (Null, Null) => {
+                self.pop_state()?;
+                self.pop((Reg(Flags as u8), Null))?;
+            }
+            _ => return Err(Fault::InvalidOperands),
+        }
+
+        Ok(())
+    }
+
+    // Clear the interrupt flag, disabling interrupts
+    fn cli(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        match operands {
+            (Operand::Null, Operand::Null) => {
+                let flags = self.read_reg(Register::Flags);
+                self.write_reg(Register::Flags, flags & !flags::INTERRUPT);
+            }
+            _ => return Err(Fault::InvalidOperands),
+        }
+
+        Ok(())
+    }
+
+    // Set the interrupt flag, enabling interrupts
+    fn sti(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        match operands {
+            (Operand::Null, Operand::Null) => {
+                let flags = self.read_reg(Register::Flags);
+                self.write_reg(Register::Flags, flags | flags::INTERRUPT);
+            }
+            _ => return Err(Fault::InvalidOperands),
+        }
+
+        Ok(())
+    }
+
+    // Call the host function registered for a syscall number
+    fn sys(&mut self, operands: (Operand, Operand)) -> Result<(), Fault> {
+        use Operand::*;
+        let number = match operands {
+            // Imm
+            (Imm(imm), Null) => imm,
+            // Reg
+            (Reg(reg), Null) => {
+                let reg = self.index_reg(reg)?;
+                self.read_reg(reg)
+            }
+            _ => return Err(Fault::InvalidOperands),
+        };
+
+        // Do what the handler did when the syscall was recorded, instead of calling it again
+        let recorded = self
+            .recorder
+            .as_ref()
+            .filter(|recorder| recorder.replaying())
+            .and_then(|recorder| recorder.syscall())
+            .cloned();
+        if let Some(syscall) = recorded {
+            for (addr, data) in syscall.writes.iter() {
+                self.write_mem(*addr, data)?;
+            }
+            for (reg, value) in syscall.regs {
+                self.write_reg(reg, value);
+            }
+            return syscall.result.map_err(Fault::Bus);
+        }
+
+        // Take the handler out while it runs, so it can borrow the CPU
+        let mut handler = self
+            .syscalls
+            .remove(&number)
+            .ok_or(Fault::InvalidSyscall(number))?;
+        let mut context = CpuContext::new(self);
+        let result = handler(&mut context);
+        let writes = context.writes;
+        self.syscalls.insert(number, handler);
+
+        if let Some(step) = self.recording_position().map(|(step, _)| step) {
+            let syscall = SyscallRecord {
+                step,
+                regs: self.registers().iter().collect(),
+                writes,
+                result,
+            };
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.record_syscall(syscall);
+            }
+        }
+
+        result.map_err(Fault::Bus)
     }
 }
@@ -0,0 +1,80 @@
+use std::fmt;
+
+// The kind of access that caused a bus fault
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+    // An instruction fetch
+    Execute,
+}
+
+impl AccessKind {
+    // Returns the code a bus fault handler finds the kind of access as
+    pub fn code(&self) -> u64 {
+        match self {
+            AccessKind::Read => 0,
+            AccessKind::Write => 1,
+            AccessKind::Execute => 2,
+        }
+    }
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessKind::Read => write!(f, "read"),
+            AccessKind::Write => write!(f, "write"),
+            AccessKind::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+// A failed access on the bus, either because no device is mapped at the address or because the device refused it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BusFault {
+    pub addr: u64,
+    pub kind: AccessKind,
+}
+
+impl BusFault {
+    // Creates a fault for a failed read at an address
+    pub fn read(addr: u64) -> Self {
+        Self {
+            addr,
+            kind: AccessKind::Read,
+        }
+    }
+
+    // Creates a fault for a failed write at an address
+    pub fn write(addr: u64) -> Self {
+        Self {
+            addr,
+            kind: AccessKind::Write,
+        }
+    }
+
+    // Creates a fault for a failed instruction fetch at an address
+    pub fn execute(addr: u64) -> Self {
+        Self {
+            addr,
+            kind: AccessKind::Execute,
+        }
+    }
+
+    // Moves the fault address from device space into bus space
+    pub(crate) fn rebase(self, start: u64) -> Self {
+        Self {
+            addr: self.addr.wrapping_add(start),
+            kind: self.kind,
+        }
+    }
+}
+
+impl fmt::Display for BusFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bus fault on {} at {:#x}", self.kind, self.addr)
+    }
+}
+
+impl std::error::Error for BusFault {}
@@ -1,3 +1,8 @@
+use std::any::Any;
+
+use super::bus_fault::BusFault;
+use crate::snapshot::Snapshot;
+
 pub struct Buffer {
     data: Vec<u8>,
 }
@@ -23,32 +28,77 @@ impl Buffer {
     pub fn size(&self) -> usize {
         self.data.len()
     }
+
+    // Returns the content of the buffer
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+// The number of cycles between ticks of the devices, unless the host sets another interval
+pub const DEFAULT_TICK_INTERVAL: u64 = 1;
+
+// Gives access to a device as its concrete type, implemented for every device
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
-// A trait for devices with generic bits.
-pub trait Device {
+// A trait for devices with generic bits. Devices save their state for snapshots through Snapshot
+pub trait Device: Snapshot + AsAny {
     /// Reads a value from an address of this device 8 bits at a time.
-    fn read(&self, addr: u64) -> u8;
+    fn read(&self, addr: u64) -> Result<u8, BusFault>;
     /// Writes a value to an address of this device 8 bits at a time.
-    fn write(&mut self, addr: u64, value: u8);
+    fn write(&mut self, addr: u64, value: u8) -> Result<(), BusFault>;
     /// Returns the size of the buffer of this device.
     fn size(&self) -> usize;
 
+    // Checks if the device is plain memory, which only reads back what was written to it.
+    // Reads of other devices are recorded to replay a run
+    fn is_memory(&self) -> bool {
+        false
+    }
+
+    // Advances the device by a number of CPU cycles, every instruction is one cycle.
+    // Devices that model time, like timers, count the cycles instead of reading a clock so runs are deterministic
+    fn tick(&mut self, _cycles: u64) {}
+
+    // Puts the device back in its power-on state
+    fn reset(&mut self) {}
+
+    // Called with the base address when the device is mapped or moved
+    fn on_attach(&mut self, _base: u64) {}
+
     // Reads a value from an address of this device 64 bits at a time.
-    fn read64(&self, addr: u64) -> u64 {
+    fn read64(&self, addr: u64) -> Result<u64, BusFault> {
         let offset = addr;
         let mut bytes = [0; 8];
-        for i in 0..8 {
-            bytes[i] = self.read(offset + i as u64);
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.read(offset + i as u64)?;
         }
-        u64::from_le_bytes(bytes)
+        Ok(u64::from_le_bytes(bytes))
     }
 
     // Writes a value to an address of this device 64 bits at a time.
-    fn write64(&mut self, addr: u64, value: u64) {
+    fn write64(&mut self, addr: u64, value: u64) -> Result<(), BusFault> {
         let offset = addr;
         for i in 0..8 {
-            self.write(offset + i, (value >> (i * 8)) as u8);
+            self.write(offset + i, (value >> (i * 8)) as u8)?;
         }
+        Ok(())
     }
 }
@@ -1,4 +1,14 @@
-use super::device::Device;
+use super::{
+    bus_fault::{AccessKind, BusFault},
+    device::Device,
+    history::{BusHistory, JournalEntry},
+    interrupts::{InterruptController, IrqLine},
+    permissions::Permissions,
+    region_index::RegionIndex,
+    watchpoint::{WatchHit, Watchpoint},
+    write_log::{MemWrite, WriteLog},
+};
+use crate::snapshot::{DeviceState, SnapshotError};
 
 // A trait for devices with generic bits
 pub trait BitsOps {
@@ -21,33 +31,57 @@ impl BitsOps for u64 {
 // A region in the device mapper that contains a device and its address range
 struct Region {
     device: Box<dyn Device>,
-    #[allow(dead_code)]
     dev_name: String,
     start: u64,
-    end: u64,
+    size: u64,
+    permissions: Permissions,
 }
 
 impl Region {
-    // Creates a new region with a device and its starting address
-    fn new(device: Box<dyn Device>, dev_name: String, start_addr: u64) -> Self {
-        let end_addr = start_addr + device.size() as u64;
+    // Creates a new region with a device, its starting address and the accesses it allows
+    fn new(
+        device: Box<dyn Device>,
+        dev_name: String,
+        start_addr: u64,
+        permissions: Permissions,
+    ) -> Self {
+        let size = device.size() as u64;
         Self {
             device,
             dev_name,
             start: start_addr,
-            end: end_addr,
+            size,
+            permissions,
         }
     }
 
-    // Checks if an address is within the address range of this region
-    fn check_addr(&self, addr: u64) -> bool {
-        addr >= self.start && addr <= self.end
+    // Returns the first and the last address of the half-open range [start, start + size), if it is not empty.
+    // The range is cut at the end of the address space
+    fn range(&self) -> Option<(u64, u64)> {
+        let last = self.start.saturating_add(self.size.checked_sub(1)?);
+        Some((self.start, last))
     }
 }
 
+// A mapped device as the host sees it, end is exclusive
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegionInfo<'a> {
+    pub name: &'a str,
+    pub start: u64,
+    pub end: u64,
+    pub permissions: Permissions,
+}
+
 // A device mapper that maps device regions to address ranges
+#[derive(Default)]
 pub struct DeviceMapper {
     regions: Vec<Region>,
+    index: RegionIndex,
+    interrupts: InterruptController,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    write_log: WriteLog,
+    history: Option<BusHistory>,
 }
 
 impl DeviceMapper {
@@ -55,78 +89,480 @@ impl DeviceMapper {
     pub fn new() -> Self {
         Self {
             regions: Vec::new(),
+            index: RegionIndex::default(),
+            interrupts: InterruptController::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            write_log: WriteLog::new(),
+            history: None,
+        }
+    }
+
+    // Maps a device to an address range that allows the given accesses
+    pub fn map(
+        &mut self,
+        mut box_device: Box<dyn Device>,
+        dev_name: String,
+        start: u64,
+        permissions: Permissions,
+    ) {
+        box_device.on_attach(start);
+        self.regions
+            .insert(0, Region::new(box_device, dev_name, start, permissions));
+        self.reindex();
+    }
+
+    // Rebuilds the index of the regions after they change
+    fn reindex(&mut self) {
+        self.index = RegionIndex::build(self.regions.iter().map(Region::range));
+    }
+
+    // Returns an IRQ line a device can raise to interrupt the CPU, if there is a line with that number
+    pub fn irq_line(&self, irq: u8) -> Option<IrqLine> {
+        self.interrupts.line(irq)
+    }
+
+    // Replaces the interrupt controller, for lines handed out before the mapper was created
+    pub(crate) fn set_interrupts(&mut self, interrupts: InterruptController) {
+        self.interrupts = interrupts;
+    }
+
+    // Returns the interrupt controller collecting the requests of the IRQ lines
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    // Watches the guest accessing a range of addresses
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    // Removes the watchpoints starting at an address
+    pub fn remove_watchpoint(&mut self, start: u64) {
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.start != start);
+    }
+
+    // Returns the first watched access since the last call
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    // Records an access of len bytes if it triggers a watchpoint and no access was recorded yet
+    fn watch(&mut self, addr: u64, len: u64, access: AccessKind) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        self.watch_hit = self
+            .watchpoints
+            .iter()
+            .find_map(|watchpoint| watchpoint.hit(addr, len, access))
+            .map(|addr| WatchHit { addr, access });
+    }
+
+    // Starts or stops recording the writes the guest makes
+    pub fn record_writes(&mut self, enabled: bool) {
+        self.write_log.set_enabled(enabled);
+    }
+
+    // Returns the writes recorded since the last call
+    pub fn take_writes(&mut self) -> Vec<MemWrite> {
+        self.write_log.take()
+    }
+
+    // Starts recording or replaying reads and journaling writes
+    pub(crate) fn start_history(&mut self, history: BusHistory) {
+        self.history = Some(history);
+    }
+
+    // Stops recording, returns the history
+    pub(crate) fn take_history(&mut self) -> Option<BusHistory> {
+        self.history.take()
+    }
+
+    pub(crate) fn history_mut(&mut self) -> Option<&mut BusHistory> {
+        self.history.as_mut()
+    }
+
+    // Undoes the journaled writes until the journal has len entries
+    pub(crate) fn rewind(&mut self, len: usize) {
+        while let Some(entry) = self.history.as_mut().and_then(|history| history.undo(len)) {
+            if let Some(region) = self.find_region_mut(entry.addr) {
+                let offset = entry.addr - region.start;
+                let _ = match entry.len {
+                    1 => region.device.write(offset, entry.old as u8),
+                    _ => region.device.write64(offset, entry.old),
+                };
+            }
+        }
+    }
+
+    // Reads len bytes, or replays the read if one of the devices is not memory and the bus replays a run
+    fn input(
+        &mut self,
+        addr: u64,
+        len: u64,
+        read: impl FnOnce(&Self) -> Result<u64, BusFault>,
+    ) -> Result<u64, BusFault> {
+        let recorded = self.history.is_some()
+            && (0..len).any(|i| {
+                self.find_region(addr.wrapping_add(i))
+                    .is_some_and(|region| !region.device.is_memory())
+            });
+        if !recorded {
+            return read(self);
+        }
+
+        let history = self.history.as_mut().unwrap();
+        if let Some(value) = history.replay_read() {
+            return value;
+        }
+        let value = read(self);
+        self.history.as_mut().unwrap().record_read(value);
+        value
+    }
+
+    // Checks if a write has to be journaled, or dropped because it would repeat the output of a replayed run
+    fn journaled(&self, addr: u64) -> (bool, bool) {
+        let history = match &self.history {
+            Some(history) => history,
+            None => return (false, false),
+        };
+        match self.find_region(addr) {
+            Some(region) if region.device.is_memory() => (true, false),
+            Some(_) => (false, history.replaying()),
+            None => (false, false),
+        }
+    }
+
+    // Advances every mapped device by a number of cycles
+    pub fn tick(&mut self, cycles: u64) {
+        for region in self.regions.iter_mut() {
+            region.device.tick(cycles);
+        }
+    }
+
+    // Puts every mapped device back in its power-on state
+    pub fn reset_devices(&mut self) {
+        for region in self.regions.iter_mut() {
+            region.device.reset();
         }
     }
 
-    // Maps a device to an address range
-    pub fn map(&mut self, box_device: Box<dyn Device>, dev_name: String, start: u64) {
+    // Saves the state of every mapped device
+    pub fn save_devices(&self) -> Vec<DeviceState> {
         self.regions
-            .insert(0, Region::new(box_device, dev_name, start));
+            .iter()
+            .map(|region| DeviceState {
+                name: region.dev_name.clone(),
+                start: region.start,
+                state: region.device.save(),
+            })
+            .collect()
+    }
+
+    // Loads the saved states into the devices mapped with the same name at the same address
+    pub fn load_devices(&mut self, devices: &[DeviceState]) -> Result<(), SnapshotError> {
+        let find = |regions: &[Region], saved: &DeviceState| {
+            regions
+                .iter()
+                .position(|region| region.dev_name == saved.name && region.start == saved.start)
+                .ok_or_else(|| SnapshotError::MissingDevice {
+                    name: saved.name.clone(),
+                    start: saved.start,
+                })
+        };
+
+        // Check the layout first, so a snapshot of another machine changes nothing
+        for saved in devices.iter() {
+            find(&self.regions, saved)?;
+        }
+        for saved in devices.iter() {
+            let i = find(&self.regions, saved)?;
+            self.regions[i].device.load(&saved.state)?;
+        }
+        Ok(())
     }
 
     // Unmaps a device from an address range
-    #[allow(dead_code)]
     pub fn unmap(&mut self, start: u64) {
         self.regions.retain(|region| region.start != start);
+        self.reindex();
+    }
+
+    // Returns the mapped devices, from the last one mapped to the first
+    pub fn regions(&self) -> impl Iterator<Item = RegionInfo<'_>> {
+        self.regions.iter().map(|region| RegionInfo {
+            name: &region.dev_name,
+            start: region.start,
+            end: region.start.saturating_add(region.size),
+            permissions: region.permissions,
+        })
     }
 
-    // Finds the region that contains an address
+    // Finds the device mapped last with a name
+    pub fn find_by_name(&self, name: &str) -> Option<RegionInfo<'_>> {
+        self.regions().find(|region| region.name == name)
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.regions
+            .iter()
+            .position(|region| region.dev_name == name)
+    }
+
+    // Unmaps the device mapped last with a name and returns it
+    pub fn unmap_by_name(&mut self, name: &str) -> Option<Box<dyn Device>> {
+        let region = self.regions.remove(self.position(name)?);
+        self.reindex();
+        Some(region.device)
+    }
+
+    // Moves the device mapped last with a name to a new base address, keeping its priority.
+    // Returns the old base address
+    pub fn remap(&mut self, name: &str, start: u64) -> Option<u64> {
+        let i = self.position(name)?;
+        let old = std::mem::replace(&mut self.regions[i].start, start);
+        self.regions[i].device.on_attach(start);
+        self.reindex();
+        Some(old)
+    }
+
+    // Returns the device mapped last with a name if it is a T
+    pub fn get<T: Device + 'static>(&self, name: &str) -> Option<&T> {
+        let region = &self.regions[self.position(name)?];
+        region.device.as_ref().as_any().downcast_ref()
+    }
+
+    // Lets the host change the device mapped last with a name if it is a T, returning what change returns.
+    // The regions are reindexed if the device changed its size
+    pub fn with_device_mut<T: Device + 'static, R>(
+        &mut self,
+        name: &str,
+        change: impl FnOnce(&mut T) -> R,
+    ) -> Option<R> {
+        let i = self.position(name)?;
+        let region = &mut self.regions[i];
+        let device = region.device.as_mut().as_any_mut().downcast_mut()?;
+        let result = change(device);
+
+        let size = region.device.size() as u64;
+        if size != region.size {
+            region.size = size;
+            self.reindex();
+        }
+        Some(result)
+    }
+
+    // Finds the region that contains an address, the last one mapped if regions overlap
     fn find_region(&self, addr: u64) -> Option<&Region> {
-        self.regions.iter().find(|region| region.check_addr(addr))
+        self.index.find(addr).map(|i| &self.regions[i])
     }
 
     // Finds the mutable reference to the region that contains an address
     fn find_region_mut(&mut self, addr: u64) -> Option<&mut Region> {
-        self.regions
-            .iter_mut()
-            .find(|region| region.check_addr(addr))
+        self.index.find(addr).map(|i| &mut self.regions[i])
     }
 
     // Reads a value from an address in the device mapper 8 bits at a time
-    pub fn read(&mut self, addr: u64) -> u8 {
-        if let Some(region) = self.find_region(addr) {
-            let offset = addr - region.start;
-            region.device.read(offset)
+    pub fn read(&mut self, addr: u64) -> Result<u8, BusFault> {
+        self.watch(addr, 1, AccessKind::Read);
+        self.input(addr, 1, |mapper| mapper.peek(addr).map(u64::from))
+            .map(|value| value as u8)
+    }
+
+    // Writes a value to an address in the device mapper 8 bits at a time
+    pub fn write(&mut self, addr: u64, value: u8) -> Result<(), BusFault> {
+        self.watch(addr, 1, AccessKind::Write);
+        self.poke(addr, value)
+    }
+
+    // Finds the region that contains an address if it allows an access, an unmapped address is a bus fault
+    fn access(&self, addr: u64, access: AccessKind) -> Result<&Region, BusFault> {
+        self.find_region(addr)
+            .filter(|region| region.permissions.allows(access))
+            .ok_or(BusFault { addr, kind: access })
+    }
+
+    // Reads a byte for an access, faults of the device are reported as faults of the access
+    fn load(&self, addr: u64, access: AccessKind) -> Result<u8, BusFault> {
+        let region = self.access(addr, access)?;
+        region
+            .device
+            .read(addr - region.start)
+            .map_err(|fault| BusFault {
+                kind: access,
+                ..fault.rebase(region.start)
+            })
+    }
+
+    // Reads 64 bits for an access, the bytes are read one at a time from the region each one
+    // resolves to when they are not all in the same region
+    fn load64(&self, addr: u64, access: AccessKind) -> Result<u64, BusFault> {
+        if let Some(i) = self.find_region64(addr) {
+            let region = &self.regions[i];
+            if !region.permissions.allows(access) {
+                return Err(BusFault { addr, kind: access });
+            }
+            return region
+                .device
+                .read64(addr - region.start)
+                .map_err(|fault| BusFault {
+                    kind: access,
+                    ..fault.rebase(region.start)
+                });
         }
-        // If no region is found, return 0
-        else {
-            0
+
+        let mut bytes = [0; 8];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.load(addr.wrapping_add(i as u64), access)?;
         }
+        Ok(u64::from_le_bytes(bytes))
     }
 
-    // Writes a value to an address in the device mapper 8 bits at a time
-    pub fn write(&mut self, addr: u64, value: u8) {
+    // Reads a value like read, without triggering watchpoints. Used by the host
+    pub fn peek(&self, addr: u64) -> Result<u8, BusFault> {
+        self.load(addr, AccessKind::Read)
+    }
+
+    // Fetches an instruction byte, the region has to allow execution
+    pub fn fetch(&self, addr: u64) -> Result<u8, BusFault> {
+        self.load(addr, AccessKind::Execute)
+    }
+
+    // Writes a value like write, without triggering watchpoints. Used by the host
+    pub fn poke(&mut self, addr: u64, value: u8) -> Result<(), BusFault> {
+        self.access(addr, AccessKind::Write)?;
+        let (journal, dropped) = self.journaled(addr);
+        if dropped {
+            return Ok(());
+        }
+
         if let Some(region) = self.find_region_mut(addr) {
             let offset = addr - region.start;
-            region.device.write(offset, value)
+            let start = region.start;
+            let old = match journal {
+                true => region.device.read(offset).ok(),
+                false => None,
+            };
+            region
+                .device
+                .write(offset, value)
+                .map_err(|fault| fault.rebase(start))?;
+
+            self.write_log.record(addr, 1, value as u64);
+            if let (Some(old), Some(history)) = (old, self.history.as_mut()) {
+                history.journal(JournalEntry {
+                    addr,
+                    len: 1,
+                    old: old as u64,
+                });
+            }
+            Ok(())
         }
-        // If no region is found, panic
+        // If no region is found, fault
         else {
-            panic!("No region/device found for address: {:#x}", addr);
+            Err(BusFault::write(addr))
         }
     }
 
+    // Finds the region that contains all the 8 bytes at an address
+    fn find_region64(&self, addr: u64) -> Option<usize> {
+        let (region, last) = self.index.span(addr)?;
+        (last - addr >= 7).then_some(region)
+    }
+
     // Reads a value from an address in the device mapper 64 bits at a time
-    pub fn read64(&mut self, addr: u64) -> u64 {
-        if let Some(region) = self.find_region(addr) {
-            let offset = addr - region.start;
-            region.device.read64(offset)
-        }
-        // If no region is found, return 0
-        else {
-            0
-        }
+    pub fn read64(&mut self, addr: u64) -> Result<u64, BusFault> {
+        self.watch(addr, 8, AccessKind::Read);
+        self.input(addr, 8, |mapper| mapper.peek64(addr))
+    }
+
+    // Reads a value like read64, without triggering watchpoints. Used by the host
+    pub fn peek64(&self, addr: u64) -> Result<u64, BusFault> {
+        self.load64(addr, AccessKind::Read)
+    }
+
+    // Fetches 64 bits of an instruction in little endian, the regions have to allow execution
+    pub fn fetch64(&self, addr: u64) -> Result<u64, BusFault> {
+        self.load64(addr, AccessKind::Execute)
     }
 
     // Writes a value to an address in the device mapper 64 bits at a time
-    pub fn write64(&mut self, addr: u64, value: u64) {
+    pub fn write64(&mut self, addr: u64, value: u64) -> Result<(), BusFault> {
+        self.watch(addr, 8, AccessKind::Write);
+
+        // Bytes in different regions are written one at a time to the device each one resolves to,
+        // after checking every byte can be written so a fault does not leave a torn store
+        if self.find_region64(addr).is_none() {
+            for i in 0..8 {
+                self.access(addr.wrapping_add(i), AccessKind::Write)?;
+            }
+            for i in 0..8 {
+                self.poke(addr.wrapping_add(i), (value >> (i * 8)) as u8)?;
+            }
+            return Ok(());
+        }
+
+        self.access(addr, AccessKind::Write)?;
+        let (journal, dropped) = self.journaled(addr);
+        if dropped {
+            return Ok(());
+        }
+
         if let Some(region) = self.find_region_mut(addr) {
             let offset = addr - region.start;
-            region.device.write64(offset, value)
+            let start = region.start;
+            let old = match journal {
+                true => region.device.read64(offset).ok(),
+                false => None,
+            };
+            region
+                .device
+                .write64(offset, value)
+                .map_err(|fault| fault.rebase(start))?;
+
+            self.write_log.record(addr, 8, value);
+            if let (Some(old), Some(history)) = (old, self.history.as_mut()) {
+                history.journal(JournalEntry { addr, len: 8, old });
+            }
+            Ok(())
         }
-        // If no region is found, panic
+        // If no region is found, fault
         else {
-            panic!("No region/device found for address: {:#x}", addr);
+            Err(BusFault::write(addr))
+        }
+    }
+
+    // Reads a little endian value of size bytes from an address in the device mapper
+    pub fn read_sized(&mut self, addr: u64, size: usize) -> Result<u64, BusFault> {
+        if size == std::mem::size_of::<u64>() {
+            return self.read64(addr);
+        }
+
+        let mut value = 0;
+        for i in 0..size {
+            let byte = self.read(addr.wrapping_add(i as u64))?;
+            value |= (byte as u64) << (i * 8);
+        }
+        Ok(value)
+    }
+
+    // Writes the low size bytes of a value to an address in the device mapper in little endian
+    pub fn write_sized(&mut self, addr: u64, value: u64, size: usize) -> Result<(), BusFault> {
+        if size == std::mem::size_of::<u64>() {
+            return self.write64(addr, value);
+        }
+
+        // Like a 64-bit store, every byte is checked before any is written so a fault does not
+        // leave a torn store
+        self.watch(addr, size as u64, AccessKind::Write);
+        for i in 0..size {
+            self.access(addr.wrapping_add(i as u64), AccessKind::Write)?;
+        }
+        for i in 0..size {
+            self.poke(addr.wrapping_add(i as u64), (value >> (i * 8)) as u8)?;
         }
+        Ok(())
     }
 }
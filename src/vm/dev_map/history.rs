@@ -0,0 +1,87 @@
+use super::bus_fault::BusFault;
+
+// The previous value of memory the guest wrote, of 1 or 8 bytes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub addr: u64,
+    pub len: u8,
+    pub old: u64,
+}
+
+// What the bus keeps to record and replay a run: the results of reading devices that are not memory,
+// and the previous values of the memory the guest wrote so the writes can be undone
+#[derive(Debug, Default)]
+pub struct BusHistory {
+    reads: Vec<Result<u64, BusFault>>,
+    cursor: usize,
+    replaying: bool,
+    journal: Vec<JournalEntry>,
+}
+
+impl BusHistory {
+    // Creates a history that replays reads recorded before
+    pub fn new(reads: Vec<Result<u64, BusFault>>) -> Self {
+        Self {
+            reads,
+            ..Self::default()
+        }
+    }
+
+    // Sets if the bus replays the recorded reads and drops the writes to devices that are not memory
+    pub fn set_replaying(&mut self, replaying: bool) {
+        self.replaying = replaying;
+    }
+
+    pub fn replaying(&self) -> bool {
+        self.replaying
+    }
+
+    // Returns the next recorded read while replaying
+    pub fn replay_read(&mut self) -> Option<Result<u64, BusFault>> {
+        if !self.replaying {
+            return None;
+        }
+        let read = self.reads.get(self.cursor).copied()?;
+        self.cursor += 1;
+        Some(read)
+    }
+
+    // Records a read, forgetting the recorded reads after it if the run went another way
+    pub fn record_read(&mut self, read: Result<u64, BusFault>) {
+        self.reads.truncate(self.cursor);
+        self.reads.push(read);
+        self.cursor += 1;
+    }
+
+    // Returns the number of reads replayed or recorded so far
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // Moves back to an earlier read
+    pub fn seek(&mut self, cursor: usize) {
+        self.cursor = cursor.min(self.reads.len());
+    }
+
+    // Records the previous value of written memory
+    pub fn journal(&mut self, entry: JournalEntry) {
+        self.journal.push(entry);
+    }
+
+    pub fn journal_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    // Removes and returns the latest journal entry past a length
+    pub fn undo(&mut self, len: usize) -> Option<JournalEntry> {
+        match self.journal.len() > len {
+            true => self.journal.pop(),
+            false => None,
+        }
+    }
+
+    // Returns all the recorded reads
+    pub fn into_reads(self) -> Vec<Result<u64, BusFault>> {
+        self.reads
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+// The number of IRQ lines of the interrupt controller
+pub const IRQ_LINES: u8 = 64;
+
+// An interrupt request line a device can raise to notify the CPU, it can be cloned and sent to other threads
+#[derive(Clone, Debug)]
+pub struct IrqLine {
+    pending: Arc<AtomicU64>,
+    irq: u8,
+}
+
+impl IrqLine {
+    // Returns the number of this line
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    // Requests an interrupt, it stays pending until the CPU takes it or the line is lowered
+    pub fn raise(&self) {
+        self.pending.fetch_or(1 << self.irq, Ordering::SeqCst);
+    }
+
+    // Withdraws a request that has not been taken yet
+    pub fn lower(&self) {
+        self.pending.fetch_and(!(1 << self.irq), Ordering::SeqCst);
+    }
+
+    // Checks if the request is still pending
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) & (1 << self.irq) != 0
+    }
+}
+
+// Collects the interrupt requests of the IRQ lines handed out to devices
+#[derive(Default)]
+pub struct InterruptController {
+    pending: Arc<AtomicU64>,
+}
+
+impl InterruptController {
+    // Creates an interrupt controller without pending requests
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the IRQ line with a number, if there is one. Lines with the same number share their requests
+    pub fn line(&self, irq: u8) -> Option<IrqLine> {
+        (irq < IRQ_LINES).then(|| IrqLine {
+            pending: self.pending.clone(),
+            irq,
+        })
+    }
+
+    // Returns the pending request with the lowest number, which has the highest priority
+    pub fn pending(&self) -> Option<u8> {
+        let pending = self.pending.load(Ordering::SeqCst);
+        match pending {
+            0 => None,
+            _ => Some(pending.trailing_zeros() as u8),
+        }
+    }
+
+    // Marks a request as taken by the CPU
+    pub fn acknowledge(&self, irq: u8) {
+        self.pending.fetch_and(!(1 << irq), Ordering::SeqCst);
+    }
+
+    // Returns the pending requests, bit n is set if IRQ n is pending
+    pub fn requests(&self) -> u64 {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    // Replaces the pending requests
+    pub fn set_requests(&self, requests: u64) {
+        self.pending.store(requests, Ordering::SeqCst);
+    }
+}
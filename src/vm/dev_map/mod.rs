@@ -1,2 +1,9 @@
+pub mod bus_fault;
 pub mod device;
 pub mod device_mapper;
+pub mod history;
+pub mod interrupts;
+pub mod permissions;
+pub mod region_index;
+pub mod watchpoint;
+pub mod write_log;
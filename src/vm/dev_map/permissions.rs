@@ -0,0 +1,54 @@
+use std::ops::BitOr;
+
+use super::bus_fault::AccessKind;
+
+// The accesses a mapped region allows, accesses it does not allow are bus faults
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub const NONE: Self = Self(0);
+    pub const READ: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 1);
+    pub const EXECUTE: Self = Self(1 << 2);
+    pub const ALL: Self = Self(Self::READ.0 | Self::WRITE.0 | Self::EXECUTE.0);
+
+    // Checks if every permission of other is in self
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // Checks if an access is allowed
+    pub fn allows(&self, access: AccessKind) -> bool {
+        match access {
+            AccessKind::Read => self.contains(Self::READ),
+            AccessKind::Write => self.contains(Self::WRITE),
+            AccessKind::Execute => self.contains(Self::EXECUTE),
+        }
+    }
+
+    // Parses permissions written like "rwx", "r-x" or "rw"
+    pub fn parse(text: &str) -> Option<Self> {
+        text.chars().try_fold(Self::NONE, |permissions, c| match c {
+            'r' => Some(permissions | Self::READ),
+            'w' => Some(permissions | Self::WRITE),
+            'x' => Some(permissions | Self::EXECUTE),
+            '-' => Some(permissions),
+            _ => None,
+        })
+    }
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
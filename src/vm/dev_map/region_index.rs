@@ -0,0 +1,91 @@
+use std::cell::Cell;
+
+// A run of addresses that resolves to a single region, last is inclusive so a segment can end at u64::MAX
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Segment {
+    start: u64,
+    last: u64,
+    region: usize,
+}
+
+impl Segment {
+    fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr <= self.last
+    }
+}
+
+// Resolves addresses to regions with a binary search over sorted segments that do not overlap.
+// The segments are painted from the lowest to the highest priority region, so the region mapped
+// last wins where regions overlap, and the segment of the last lookup is cached
+#[derive(Debug, Default)]
+pub struct RegionIndex {
+    segments: Vec<Segment>,
+    last_hit: Cell<Option<Segment>>,
+}
+
+impl RegionIndex {
+    // Builds the index from the inclusive address ranges of the regions, from the highest priority to the lowest.
+    // Empty regions have no range and no addresses
+    pub fn build(ranges: impl Iterator<Item = Option<(u64, u64)>>) -> Self {
+        let mut index = Self::default();
+        let ranges: Vec<Option<(u64, u64)>> = ranges.collect();
+        for (region, range) in ranges.iter().enumerate().rev() {
+            if let Some((start, last)) = *range {
+                index.paint(Segment {
+                    start,
+                    last,
+                    region,
+                });
+            }
+        }
+        index
+    }
+
+    // Covers the addresses of a segment with it, trimming the segments it overlaps
+    fn paint(&mut self, segment: Segment) {
+        let mut segments = Vec::with_capacity(self.segments.len() + 2);
+        for &other in self.segments.iter() {
+            if other.last < segment.start || other.start > segment.last {
+                segments.push(other);
+                continue;
+            }
+            if other.start < segment.start {
+                segments.push(Segment {
+                    last: segment.start - 1,
+                    ..other
+                });
+            }
+            if other.last > segment.last {
+                segments.push(Segment {
+                    start: segment.last + 1,
+                    ..other
+                });
+            }
+        }
+        segments.push(segment);
+        segments.sort_unstable_by_key(|segment| segment.start);
+        self.segments = segments;
+    }
+
+    // Returns the index of the region an address resolves to
+    pub fn find(&self, addr: u64) -> Option<usize> {
+        self.span(addr).map(|(region, _)| region)
+    }
+
+    // Returns the index of the region an address resolves to and the last address that resolves to it
+    // without interruption
+    pub fn span(&self, addr: u64) -> Option<(usize, u64)> {
+        if let Some(hit) = self.last_hit.get().filter(|hit| hit.contains(addr)) {
+            return Some((hit.region, hit.last));
+        }
+
+        let next = self
+            .segments
+            .partition_point(|segment| segment.start <= addr);
+        let segment = self.segments[..next]
+            .last()
+            .filter(|segment| segment.contains(addr))?;
+        self.last_hit.set(Some(*segment));
+        Some((segment.region, segment.last))
+    }
+}
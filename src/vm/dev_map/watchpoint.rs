@@ -0,0 +1,53 @@
+use super::bus_fault::AccessKind;
+
+// The accesses a watchpoint stops on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    // Checks if an access triggers this kind of watchpoint
+    pub fn matches(&self, access: AccessKind) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, AccessKind::Read)
+                | (WatchKind::Write, AccessKind::Write)
+        )
+    }
+}
+
+// A range of addresses the guest is watched accessing, instruction fetches are not watched
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u64,
+    pub len: u64,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn new(start: u64, len: u64, kind: WatchKind) -> Self {
+        Self { start, len, kind }
+    }
+
+    // Returns the first watched address an access of len bytes at addr touches, if it triggers the watchpoint
+    pub fn hit(&self, addr: u64, len: u64, access: AccessKind) -> Option<u64> {
+        let end = self.start.saturating_add(self.len);
+        let access_end = addr.saturating_add(len);
+        if self.kind.matches(access) && addr < end && self.start < access_end {
+            Some(addr.max(self.start))
+        } else {
+            None
+        }
+    }
+}
+
+// A watched access
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u64,
+    pub access: AccessKind,
+}
@@ -0,0 +1,49 @@
+// A write the guest made to memory, of up to 8 bytes in little endian
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemWrite {
+    pub addr: u64,
+    pub len: u64,
+    pub value: u64,
+}
+
+// Collects the writes the guest makes while enabled, used to trace what an instruction wrote
+#[derive(Debug, Default)]
+pub struct WriteLog {
+    enabled: bool,
+    writes: Vec<MemWrite>,
+}
+
+impl WriteLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts or stops collecting writes, stopping drops the collected ones
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.writes.clear();
+        }
+    }
+
+    // Records a write, bytes written one after another are merged into a single write
+    pub fn record(&mut self, addr: u64, len: u64, value: u64) {
+        if !self.enabled {
+            return;
+        }
+
+        if let Some(last) = self.writes.last_mut() {
+            if len == 1 && last.len < 8 && last.addr.wrapping_add(last.len) == addr {
+                last.value |= value << (last.len * 8);
+                last.len += 1;
+                return;
+            }
+        }
+        self.writes.push(MemWrite { addr, len, value });
+    }
+
+    // Returns the writes recorded since the last call
+    pub fn take(&mut self) -> Vec<MemWrite> {
+        std::mem::take(&mut self.writes)
+    }
+}
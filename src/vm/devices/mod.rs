@@ -1,4 +1,30 @@
+use crate::dev_map::device::Device;
+
 pub mod ram;
 pub mod registers;
 pub mod rom;
+pub mod sparse_ram;
 pub mod stdout;
+
+// The name the RAM of a CPU is mapped under
+pub const RAM_NAME: &str = "RAM";
+
+// The RAM a CPU is created with
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RamKind {
+    // A buffer allocated up front
+    #[default]
+    Dense,
+    // Pages allocated on the first write to them, for large address spaces
+    Sparse,
+}
+
+impl RamKind {
+    // Creates RAM of this kind with size bytes
+    pub fn create(&self, size: usize) -> Box<dyn Device> {
+        match self {
+            RamKind::Dense => Box::new(ram::Ram::new(size)),
+            RamKind::Sparse => Box::new(sparse_ram::SparseRam::new(size)),
+        }
+    }
+}
@@ -1,4 +1,10 @@
-use crate::dev_map::device::{Buffer, Device};
+use crate::{
+    dev_map::{
+        bus_fault::BusFault,
+        device::{Buffer, Device},
+    },
+    snapshot::{self, Snapshot, SnapshotError},
+};
 
 pub struct Ram {
     buffer: Buffer,
@@ -13,17 +19,33 @@ impl Ram {
 }
 
 impl Device for Ram {
-    fn read(&self, addr: u64) -> u8 {
+    fn read(&self, addr: u64) -> Result<u8, BusFault> {
         let offset = addr as usize;
-        self.buffer.read(offset)
+        Ok(self.buffer.read(offset))
     }
 
-    fn write(&mut self, addr: u64, value: u8) {
+    fn write(&mut self, addr: u64, value: u8) -> Result<(), BusFault> {
         let offset = addr as usize;
         self.buffer.write(offset, value);
+        Ok(())
     }
 
     fn size(&self) -> usize {
         self.buffer.size()
     }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+}
+
+// RAM is saved sparsely, as the pages that are not all zero
+impl Snapshot for Ram {
+    fn save(&self) -> Vec<u8> {
+        snapshot::save_memory(self.buffer.data())
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        snapshot::load_memory(self.buffer.data_mut(), state)
+    }
 }
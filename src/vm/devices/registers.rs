@@ -14,8 +14,8 @@ impl Registers {
     pub fn read(&self, addr: u64) -> u64 {
         let offset = addr as usize;
         let mut bytes = [0; 8];
-        for i in 0..8 {
-            bytes[i] = self.buffer.read(offset + i as usize);
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.buffer.read(offset + i);
         }
         u64::from_le_bytes(bytes)
     }
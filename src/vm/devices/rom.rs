@@ -1,4 +1,10 @@
-use crate::dev_map::device::{Buffer, Device};
+use crate::{
+    dev_map::{
+        bus_fault::BusFault,
+        device::{Buffer, Device},
+    },
+    snapshot::{self, Snapshot, SnapshotError},
+};
 
 pub struct Rom {
     buffer: Buffer,
@@ -21,21 +27,43 @@ impl Rom {
 }
 
 impl Device for Rom {
-    fn read(&self, addr: u64) -> u8 {
+    fn read(&self, addr: u64) -> Result<u8, BusFault> {
         let offset = addr as usize;
-        self.buffer.read(offset)
+        Ok(self.buffer.read(offset))
     }
 
-    fn write(&mut self, addr: u64, value: u8) {
+    fn write(&mut self, addr: u64, value: u8) -> Result<(), BusFault> {
         if self.writeable {
             let offset = addr as usize;
             self.buffer.write(offset, value);
-        } else {
-            panic!("Attempted to write to ROM");
+            Ok(())
+        }
+        // Writing to ROM is a bus fault
+        else {
+            Err(BusFault::write(addr))
         }
     }
 
     fn size(&self) -> usize {
         self.buffer.size()
     }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+}
+
+// ROM is saved as whether it is writeable followed by its pages that are not all zero
+impl Snapshot for Rom {
+    fn save(&self) -> Vec<u8> {
+        let mut state = vec![self.writeable as u8];
+        state.extend(snapshot::save_memory(self.buffer.data()));
+        state
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        let (writeable, pages) = state.split_first().ok_or(SnapshotError::InvalidState)?;
+        self.writeable = *writeable != 0;
+        snapshot::load_memory(self.buffer.data_mut(), pages)
+    }
 }
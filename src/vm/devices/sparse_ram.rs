@@ -0,0 +1,93 @@
+use hashbrown::HashMap;
+
+use crate::{
+    dev_map::{bus_fault::BusFault, device::Device},
+    snapshot::{self, Snapshot, SnapshotError, SNAPSHOT_PAGE_SIZE},
+};
+
+// Pages match the pages of snapshots, so they are saved as they are
+pub const PAGE_SIZE: usize = SNAPSHOT_PAGE_SIZE;
+
+// RAM that allocates a page on the first write to it, untouched pages read as zero
+pub struct SparseRam {
+    size: usize,
+    pages: HashMap<usize, Box<[u8; PAGE_SIZE]>>,
+}
+
+impl SparseRam {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            pages: HashMap::new(),
+        }
+    }
+
+    // Returns the number of bytes of the allocated pages
+    pub fn resident_size(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+}
+
+impl Device for SparseRam {
+    fn read(&self, addr: u64) -> Result<u8, BusFault> {
+        let offset = addr as usize;
+        if offset >= self.size {
+            return Err(BusFault::read(addr));
+        }
+
+        let page = self.pages.get(&(offset / PAGE_SIZE));
+        Ok(page.map_or(0, |page| page[offset % PAGE_SIZE]))
+    }
+
+    fn write(&mut self, addr: u64, value: u8) -> Result<(), BusFault> {
+        let offset = addr as usize;
+        if offset >= self.size {
+            return Err(BusFault::write(addr));
+        }
+
+        // Writing zero to an untouched page leaves it untouched
+        let index = offset / PAGE_SIZE;
+        if value == 0 && !self.pages.contains_key(&index) {
+            return Ok(());
+        }
+        let page = self
+            .pages
+            .entry(index)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        page[offset % PAGE_SIZE] = value;
+        Ok(())
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+}
+
+// Saved like contiguous RAM, as the pages that are not all zero
+impl Snapshot for SparseRam {
+    fn save(&self) -> Vec<u8> {
+        let mut indices: Vec<usize> = self.pages.keys().copied().collect();
+        indices.sort_unstable();
+
+        let size = self.size;
+        snapshot::save_pages(indices.into_iter().map(|index| {
+            let len = PAGE_SIZE.min(size - index * PAGE_SIZE);
+            (index, &self.pages[&index][..len])
+        }))
+    }
+
+    fn load(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        let mut pages = HashMap::new();
+        snapshot::load_pages(state, self.size, |index, data| {
+            let mut page = Box::new([0; PAGE_SIZE]);
+            page[..data.len()].copy_from_slice(data);
+            pages.insert(index, page);
+        })?;
+        self.pages = pages;
+        Ok(())
+    }
+}
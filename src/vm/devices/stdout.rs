@@ -1,8 +1,12 @@
-use crate::dev_map::device::Device;
+use crate::{
+    dev_map::{bus_fault::BusFault, device::Device},
+    snapshot::Snapshot,
+};
 
 pub const STDOUT_SIZE: usize = 0x1000;
 pub const STDOUT_NEWLINE: u8 = 0xFF;
 
+#[derive(Default)]
 pub struct Stdout;
 
 impl Stdout {
@@ -12,18 +16,23 @@ impl Stdout {
 }
 
 impl Device for Stdout {
-    fn read(&self, _addr: u64) -> u8 {
-        panic!("Cannot read from stdout");
+    // Stdout is write only
+    fn read(&self, addr: u64) -> Result<u8, BusFault> {
+        Err(BusFault::read(addr))
     }
 
-    fn write(&mut self, _addr: u64, value: u8) {
+    fn write(&mut self, _addr: u64, value: u8) -> Result<(), BusFault> {
         match value {
-            STDOUT_NEWLINE => print!("\n"),
+            STDOUT_NEWLINE => println!(),
             _ => print!("{}", value as char),
         }
+        Ok(())
     }
 
     fn size(&self) -> usize {
         STDOUT_SIZE
     }
 }
+
+// Stdout has no state
+impl Snapshot for Stdout {}
@@ -0,0 +1,155 @@
+use std::fmt;
+
+use crate::{
+    dev_map::bus_fault::{AccessKind, BusFault},
+    opcodes::{AddrMode, Opcode},
+    vectors,
+};
+
+// An error raised while executing guest code, every variant carries the address of the faulting instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    InvalidOpcode {
+        ip: u64,
+        byte: u8,
+    },
+    InvalidAddrMode {
+        ip: u64,
+        byte: u8,
+    },
+    InvalidOperands {
+        ip: u64,
+        opcode: Opcode,
+        mode: AddrMode,
+    },
+    InvalidRegister {
+        ip: u64,
+        index: u8,
+    },
+    BusFault {
+        ip: u64,
+        addr: u64,
+        kind: AccessKind,
+    },
+    DivideByZero {
+        ip: u64,
+    },
+    StackOverflow {
+        ip: u64,
+    },
+    StackUnderflow {
+        ip: u64,
+    },
+    UnhandledInterrupt {
+        ip: u64,
+        vector: u64,
+    },
+    InvalidSyscall {
+        ip: u64,
+        number: u64,
+    },
+}
+
+impl VmError {
+    // Returns the address of the instruction that faulted
+    pub fn ip(&self) -> u64 {
+        use VmError::*;
+        match self {
+            InvalidOpcode { ip, .. }
+            | InvalidAddrMode { ip, .. }
+            | InvalidOperands { ip, .. }
+            | InvalidRegister { ip, .. }
+            | BusFault { ip, .. }
+            | DivideByZero { ip }
+            | StackOverflow { ip }
+            | StackUnderflow { ip }
+            | UnhandledInterrupt { ip, .. }
+            | InvalidSyscall { ip, .. } => *ip,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use VmError::*;
+        match self {
+            InvalidOpcode { ip, byte } => write!(f, "invalid opcode {:#x} at {:#x}", byte, ip),
+            InvalidAddrMode { ip, byte } => {
+                write!(f, "invalid address mode {:#x} at {:#x}", byte, ip)
+            }
+            InvalidOperands { ip, opcode, mode } => {
+                write!(
+                    f,
+                    "invalid operands {:?} for {:?} at {:#x}",
+                    mode, opcode, ip
+                )
+            }
+            InvalidRegister { ip, index } => {
+                write!(f, "invalid register {:#x} at {:#x}", index, ip)
+            }
+            BusFault { ip, addr, kind } => {
+                write!(f, "bus fault on {} at {:#x} by {:#x}", kind, addr, ip)
+            }
+            DivideByZero { ip } => write!(f, "divide by zero at {:#x}", ip),
+            StackOverflow { ip } => write!(f, "stack overflow at {:#x}", ip),
+            StackUnderflow { ip } => write!(f, "stack underflow at {:#x}", ip),
+            UnhandledInterrupt { ip, vector } => {
+                write!(f, "unhandled interrupt {:#x} at {:#x}", vector, ip)
+            }
+            InvalidSyscall { ip, number } => {
+                write!(f, "invalid syscall {:#x} at {:#x}", number, ip)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+// A fault raised while executing an instruction, before it is tied to the faulting instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    InvalidOperands,
+    InvalidRegister(u8),
+    Bus(BusFault),
+    DivideByZero,
+    StackOverflow,
+    StackUnderflow,
+    UnhandledInterrupt(u64),
+    InvalidSyscall(u64),
+}
+
+impl Fault {
+    // Returns the vector the guest can handle the fault with, if any
+    pub(crate) fn vector(&self) -> Option<u64> {
+        match self {
+            Fault::DivideByZero => Some(vectors::DIVIDE_BY_ZERO),
+            Fault::InvalidSyscall(_) => Some(vectors::INVALID_SYSCALL),
+            Fault::Bus(_) => Some(vectors::BUS_FAULT),
+            _ => None,
+        }
+    }
+
+    // Attaches the faulting instruction to the fault
+    pub(crate) fn at(self, ip: u64, opcode: Opcode, mode: AddrMode) -> VmError {
+        match self {
+            Fault::InvalidOperands => VmError::InvalidOperands { ip, opcode, mode },
+            Fault::InvalidRegister(index) => VmError::InvalidRegister { ip, index },
+            Fault::Bus(fault) => VmError::BusFault {
+                ip,
+                addr: fault.addr,
+                kind: fault.kind,
+            },
+            Fault::DivideByZero => VmError::DivideByZero { ip },
+            Fault::StackOverflow => VmError::StackOverflow { ip },
+            Fault::StackUnderflow => VmError::StackUnderflow { ip },
+            Fault::UnhandledInterrupt(vector) => VmError::UnhandledInterrupt { ip, vector },
+            Fault::InvalidSyscall(number) => VmError::InvalidSyscall { ip, number },
+        }
+    }
+}
+
+impl From<BusFault> for Fault {
+    fn from(fault: BusFault) -> Self {
+        Fault::Bus(fault)
+    }
+}
@@ -0,0 +1,418 @@
+// A stub speaking the GDB Remote Serial Protocol, so gdb and IDE front-ends can debug the CPU
+
+pub mod packet;
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    dev_map::{bus_fault::AccessKind, watchpoint::WatchKind},
+    register::Register,
+    Cpu, StopReason, VmError,
+};
+
+// Signals reported to the debugger when the target stops
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+// How many instructions run between checks for an interrupt from the debugger
+const INTERRUPT_POLL: u64 = 1024;
+
+// The most bytes a single memory read returns, half the packet size in hex
+const MAX_MEMORY_READ: u64 = 0x2000;
+
+// A connection to a debugger
+pub trait Connection: Read + Write {
+    // Checks without blocking if the debugger sent an interrupt while the target runs
+    fn interrupted(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+impl Connection for TcpStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let mut byte = [0];
+        self.set_nonblocking(true)?;
+        let peeked = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+
+        match peeked {
+            Ok(1) if byte[0] == packet::INTERRUPT => {
+                self.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+// What the stub does after a packet
+enum Reply {
+    Packet(Vec<u8>),
+    // Reply and end the session
+    Last(Vec<u8>),
+    // End the session without a reply
+    Close,
+}
+
+// Serves a debugger on a connection, the CPU is stopped whenever a packet is handled
+pub struct GdbStub<C: Connection> {
+    conn: C,
+    ack: bool,
+    last_stop: Vec<u8>,
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(conn: C) -> Self {
+        Self {
+            conn,
+            ack: true,
+            last_stop: signal_reply(SIGTRAP),
+        }
+    }
+
+    // Returns the connection
+    pub fn into_inner(self) -> C {
+        self.conn
+    }
+
+    // Handles packets until the debugger detaches, kills the target or disconnects
+    pub fn serve(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        loop {
+            let packet = match packet::read_packet(&mut self.conn, self.ack) {
+                Ok(packet) => packet,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err),
+            };
+
+            match self.handle(cpu, &packet)? {
+                Reply::Packet(reply) => packet::write_packet(&mut self.conn, &reply, self.ack)?,
+                Reply::Last(reply) => {
+                    return packet::write_packet(&mut self.conn, &reply, self.ack);
+                }
+                Reply::Close => return Ok(()),
+            }
+
+            // Acks stop after the reply to the packet turning them off
+            if packet == b"QStartNoAckMode" {
+                self.ack = false;
+            }
+        }
+    }
+
+    // Handles a packet and returns the reply, an empty reply means the packet is not supported
+    fn handle(&mut self, cpu: &mut Cpu, packet: &[u8]) -> io::Result<Reply> {
+        let (command, args) = match packet.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(Reply::Packet(Vec::new())),
+        };
+
+        let reply = match command {
+            b'?' => self.last_stop.clone(),
+            b'g' => read_registers(cpu),
+            b'G' => write_registers(cpu, args),
+            b'p' => read_register(cpu, args),
+            b'P' => write_register(cpu, args),
+            b'm' => read_memory(cpu, args),
+            b'M' => write_memory(cpu, args),
+            b's' | b'c' => {
+                // An address to resume from is optional
+                if let Some(addr) = packet::parse_hex(args) {
+                    cpu.set_reg(Register::InstructionPointer, addr);
+                }
+                let stop = match command {
+                    b's' => step(cpu),
+                    _ => self.resume(cpu)?,
+                };
+                self.last_stop = stop.clone();
+                stop
+            }
+            b'b' => {
+                // Execute backwards through the recorded run
+                let stop = match args {
+                    b"s" => stop_reply(cpu.reverse_step()),
+                    b"c" => stop_reply(cpu.reverse_continue()),
+                    _ => return Ok(Reply::Packet(Vec::new())),
+                };
+                self.last_stop = stop.clone();
+                stop
+            }
+            b'Z' | b'z' => breakpoint(cpu, command == b'Z', args),
+            b'H' | b'T' => ok(),
+            b'q' | b'Q' => query(packet),
+            b'D' => return Ok(Reply::Last(ok())),
+            b'k' => return Ok(Reply::Close),
+            _ => Vec::new(),
+        };
+
+        Ok(Reply::Packet(reply))
+    }
+
+    // Run until the CPU stops or the debugger interrupts it
+    fn resume(&mut self, cpu: &mut Cpu) -> io::Result<Vec<u8>> {
+        let conn = &mut self.conn;
+        let mut executed: u64 = 0;
+        let mut poll_error = None;
+        let result = cpu.run_until(|_| {
+            executed += 1;
+            if !executed.is_multiple_of(INTERRUPT_POLL) {
+                return false;
+            }
+            conn.interrupted().unwrap_or_else(|err| {
+                poll_error = Some(err);
+                true
+            })
+        });
+
+        if let Some(err) = poll_error {
+            return Err(err);
+        }
+
+        Ok(stop_reply(result))
+    }
+}
+
+// Waits for a debugger to connect on an address and serves it until it detaches
+pub fn listen<A: ToSocketAddrs>(addr: A, cpu: &mut Cpu) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub::new(stream).serve(cpu)
+}
+
+// Returns the target description, the registers in the order of the g packet
+pub fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <feature name=\"org.slang.core\">\n",
+    );
+    for (regnum, reg) in Register::all().iter().enumerate() {
+        let kind = match reg {
+            Register::InstructionPointer => "code_ptr",
+            Register::StackPointer | Register::FramePointer => "data_ptr",
+            _ => "uint64",
+        };
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"64\" type=\"{}\" regnum=\"{}\"/>\n",
+            reg.mnemonic(),
+            kind,
+            regnum
+        ));
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+fn ok() -> Vec<u8> {
+    b"OK".to_vec()
+}
+
+fn error() -> Vec<u8> {
+    b"E01".to_vec()
+}
+
+fn signal_reply(signal: u8) -> Vec<u8> {
+    format!("S{:02x}", signal).into_bytes()
+}
+
+// The target exited, which is how a halted CPU looks to the debugger
+fn exit_reply() -> Vec<u8> {
+    b"W00".to_vec()
+}
+
+// Returns the signal a fault is reported as
+fn fault_signal(err: &VmError) -> u8 {
+    match err {
+        VmError::InvalidOpcode { .. }
+        | VmError::InvalidAddrMode { .. }
+        | VmError::InvalidOperands { .. }
+        | VmError::InvalidRegister { .. }
+        | VmError::InvalidSyscall { .. } => SIGILL,
+        VmError::BusFault { .. }
+        | VmError::StackOverflow { .. }
+        | VmError::StackUnderflow { .. } => SIGSEGV,
+        VmError::DivideByZero { .. } => SIGFPE,
+        VmError::UnhandledInterrupt { .. } => SIGTRAP,
+    }
+}
+
+// Returns the reply telling the debugger why the CPU stopped
+fn stop_reply(result: Result<StopReason, VmError>) -> Vec<u8> {
+    match result {
+        Ok(StopReason::Halted) => exit_reply(),
+        Ok(StopReason::Breakpoint { .. }) => b"T05swbreak:;".to_vec(),
+        Ok(StopReason::Watchpoint { addr, access }) => {
+            let kind = match access {
+                AccessKind::Read | AccessKind::Execute => "rwatch",
+                AccessKind::Write => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr).into_bytes()
+        }
+        Ok(StopReason::Condition) => signal_reply(SIGINT),
+        Ok(StopReason::InstructionLimit) => signal_reply(SIGTRAP),
+        Ok(StopReason::RecordingStart) => format!("T{:02x}replaylog:begin;", SIGTRAP).into_bytes(),
+        Err(err) => signal_reply(fault_signal(&err)),
+    }
+}
+
+// Execute a single instruction
+fn step(cpu: &mut Cpu) -> Vec<u8> {
+    match cpu.step() {
+        Ok(outcome) if outcome.halted() => exit_reply(),
+        Ok(_) => signal_reply(SIGTRAP),
+        Err(err) => signal_reply(fault_signal(&err)),
+    }
+}
+
+fn read_registers(cpu: &Cpu) -> Vec<u8> {
+    let bytes: Vec<u8> = Register::all()
+        .iter()
+        .flat_map(|reg| cpu.reg(*reg).to_le_bytes())
+        .collect();
+    packet::to_hex(&bytes).into_bytes()
+}
+
+// Writes every register, the payload must hold exactly one value per register
+fn write_registers(cpu: &mut Cpu, args: &[u8]) -> Vec<u8> {
+    let bytes = match packet::from_hex(args) {
+        Some(bytes) if bytes.len() == Register::all().len() * 8 => bytes,
+        _ => return error(),
+    };
+    for (reg, value) in Register::all().iter().zip(bytes.chunks_exact(8)) {
+        cpu.set_reg(*reg, u64::from_le_bytes(value.try_into().unwrap()));
+    }
+    ok()
+}
+
+// Finds a register by its number in the target description
+fn register(regnum: &[u8]) -> Option<Register> {
+    let regnum = packet::parse_hex(regnum)?;
+    Register::all().get(regnum as usize).copied()
+}
+
+fn read_register(cpu: &Cpu, args: &[u8]) -> Vec<u8> {
+    match register(args) {
+        Some(reg) => packet::to_hex(&cpu.reg(reg).to_le_bytes()).into_bytes(),
+        None => error(),
+    }
+}
+
+fn write_register(cpu: &mut Cpu, args: &[u8]) -> Vec<u8> {
+    let (regnum, value) = match split(args, b'=') {
+        Some(parts) => parts,
+        None => return error(),
+    };
+    let value = packet::from_hex(value).and_then(|bytes| <[u8; 8]>::try_from(bytes).ok());
+    match (register(regnum), value) {
+        (Some(reg), Some(value)) => {
+            cpu.set_reg(reg, u64::from_le_bytes(value));
+            ok()
+        }
+        _ => error(),
+    }
+}
+
+// Splits arguments at the first separator
+fn split(args: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let at = args.iter().position(|byte| *byte == separator)?;
+    Some((&args[..at], &args[at + 1..]))
+}
+
+// Parses addr,len
+fn range(args: &[u8]) -> Option<(u64, u64)> {
+    let (addr, len) = split(args, b',')?;
+    Some((packet::parse_hex(addr)?, packet::parse_hex(len)?))
+}
+
+fn read_memory(cpu: &mut Cpu, args: &[u8]) -> Vec<u8> {
+    let (addr, len) = match range(args) {
+        Some(range) => range,
+        None => return error(),
+    };
+    match cpu.read_mem(addr, len.min(MAX_MEMORY_READ) as usize) {
+        Ok(bytes) => packet::to_hex(&bytes).into_bytes(),
+        Err(_) => error(),
+    }
+}
+
+fn write_memory(cpu: &mut Cpu, args: &[u8]) -> Vec<u8> {
+    let (range, data) = match split(args, b':') {
+        Some((bounds, data)) => (range(bounds), packet::from_hex(data)),
+        None => return error(),
+    };
+    match (range, data) {
+        (Some((addr, len)), Some(data)) if data.len() as u64 == len => {
+            match cpu.write_mem(addr, &data) {
+                Ok(()) => ok(),
+                Err(_) => error(),
+            }
+        }
+        _ => error(),
+    }
+}
+
+// Inserts or removes a breakpoint or watchpoint, Ztype,addr,kind
+fn breakpoint(cpu: &mut Cpu, insert: bool, args: &[u8]) -> Vec<u8> {
+    let mut parts = args.split(|byte| *byte == b',');
+    let fields = (
+        parts.next().and_then(packet::parse_hex),
+        parts.next().and_then(packet::parse_hex),
+        parts.next().and_then(packet::parse_hex),
+    );
+    let (kind, addr, len) = match fields {
+        (Some(kind), Some(addr), Some(len)) => (kind, addr, len),
+        _ => return error(),
+    };
+
+    let watch = match kind {
+        0 | 1 => None,
+        2 => Some(WatchKind::Write),
+        3 => Some(WatchKind::Read),
+        4 => Some(WatchKind::ReadWrite),
+        _ => return Vec::new(),
+    };
+    match (watch, insert) {
+        (None, true) => cpu.add_breakpoint(addr),
+        (None, false) => cpu.remove_breakpoint(addr),
+        (Some(watch), true) => cpu.add_watchpoint(addr, len, watch),
+        (Some(_), false) => cpu.remove_watchpoint(addr),
+    }
+    ok()
+}
+
+// Answers the general queries and settings
+fn query(packet: &[u8]) -> Vec<u8> {
+    if packet.starts_with(b"qSupported") {
+        return b"PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_vec();
+    }
+    if let Some(args) = packet.strip_prefix(b"qXfer:features:read:target.xml:") {
+        return match range(args) {
+            Some((offset, len)) => {
+                let xml = target_xml().into_bytes();
+                let start = (offset as usize).min(xml.len());
+                let end = start.saturating_add(len as usize).min(xml.len());
+                let more = if end < xml.len() { b'm' } else { b'l' };
+                [&[more][..], &xml[start..end]].concat()
+            }
+            None => error(),
+        };
+    }
+
+    match packet {
+        b"QStartNoAckMode" => ok(),
+        b"qAttached" => b"1".to_vec(),
+        b"qC" => b"QC1".to_vec(),
+        b"qfThreadInfo" => b"m1".to_vec(),
+        b"qsThreadInfo" => b"l".to_vec(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::{self, Read, Write};
+
+// Interrupt byte the debugger sends outside of packets while the target runs
+pub const INTERRUPT: u8 = 0x03;
+
+// Computes the checksum of the data of a packet, the sum of its bytes modulo 256
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// Encodes data into a packet, escaping the bytes that frame packets
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for byte in data {
+        match byte {
+            b'#' | b'$' | b'}' | b'*' => escaped.extend([b'}', byte ^ 0x20]),
+            _ => escaped.push(*byte),
+        }
+    }
+
+    let mut packet = Vec::with_capacity(escaped.len() + 4);
+    packet.push(b'$');
+    packet.extend(&escaped);
+    packet.extend(format!("#{:02x}", checksum(&escaped)).as_bytes());
+    packet
+}
+
+// Reads one byte, failing if the connection closed
+fn read_byte<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+// Reads the next packet with a valid checksum, acknowledging it unless acks are off.
+// Acks and interrupts between packets are skipped
+pub fn read_packet<C: Read + Write>(conn: &mut C, ack: bool) -> io::Result<Vec<u8>> {
+    loop {
+        while read_byte(conn)? != b'$' {}
+
+        // The checksum covers the escaped data
+        let mut raw = Vec::new();
+        loop {
+            match read_byte(conn)? {
+                b'#' => break,
+                byte => raw.push(byte),
+            }
+        }
+        let digits = [read_byte(conn)?, read_byte(conn)?];
+        let expected = std::str::from_utf8(&digits)
+            .ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+
+        let valid = expected == Some(checksum(&raw));
+        if ack {
+            conn.write_all(if valid { b"+" } else { b"-" })?;
+            conn.flush()?;
+        }
+        if valid || !ack {
+            return Ok(unescape(&raw));
+        }
+    }
+}
+
+// Removes the escapes from the data of a packet
+fn unescape(raw: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(raw.len());
+    let mut bytes = raw.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => data.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => data.push(*byte),
+        }
+    }
+    data
+}
+
+// Writes a packet, resending it until the debugger acknowledges it unless acks are off
+pub fn write_packet<C: Read + Write>(conn: &mut C, data: &[u8], ack: bool) -> io::Result<()> {
+    let packet = encode(data);
+    loop {
+        conn.write_all(&packet)?;
+        conn.flush()?;
+        if !ack {
+            return Ok(());
+        }
+
+        // Skip interrupts that raced the reply
+        loop {
+            match read_byte(conn)? {
+                b'+' => return Ok(()),
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+// Encodes bytes as lowercase hex
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Decodes hex into bytes
+pub fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+// Parses a hex number like the addresses and lengths in packets
+pub fn parse_hex(hex: &[u8]) -> Option<u64> {
+    let hex = std::str::from_utf8(hex).ok()?;
+    u64::from_str_radix(hex, 16).ok()
+}
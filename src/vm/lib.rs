@@ -1,9 +1,26 @@
 #![allow(dead_code)] // TODO: Temporary until it doesn't give warnings
 
 mod cpu;
-mod dev_map;
+pub mod dev_map;
 pub mod devices;
+pub mod error;
+pub mod gdb;
+pub mod machine;
+pub mod machine_file;
 pub mod opcodes;
 pub mod register;
+pub mod replay;
+pub mod snapshot;
+pub mod step;
+pub mod syscall;
+pub mod trace;
+pub mod vectors;
+
+#[cfg(test)]
+mod tests;
 
 pub use cpu::Cpu;
+pub use error::VmError;
+pub use machine::{ConfigError, MachineConfig};
+pub use step::{BreakCondition, Flow, Frame, StepOutcome, StopReason};
+pub use syscall::{CpuContext, SyscallHandler};
@@ -0,0 +1,288 @@
+use std::fmt;
+
+use hashbrown::HashSet;
+
+use crate::{
+    dev_map::{
+        device::Device,
+        interrupts::{InterruptController, IrqLine},
+        permissions::Permissions,
+    },
+    devices::{rom::Rom, RamKind, RAM_NAME},
+    register::Register,
+    Cpu,
+};
+
+// The size of a stack slot, the first push writes it at the initial stack pointer
+const STACK_SLOT: u64 = std::mem::size_of::<u64>() as u64;
+
+// A ROM declared in a machine, flashed with its image when the machine is built
+struct RomImage {
+    name: String,
+    base: u64,
+    size: usize,
+    image: Vec<u8>,
+}
+
+// A device declared in a machine
+struct DeviceEntry {
+    name: String,
+    base: u64,
+    device: Box<dyn Device>,
+    permissions: Permissions,
+}
+
+// The layout of a machine: RAM mapped at 0, the ROMs and devices mapped over it and the initial
+// stack and instruction pointers. The layout is checked when the CPU is built
+pub struct MachineConfig {
+    ram_size: usize,
+    ram_kind: RamKind,
+    roms: Vec<RomImage>,
+    devices: Vec<DeviceEntry>,
+    stack_pointer: Option<u64>,
+    entry: u64,
+    // The interrupt controller of the machine, so devices can get their lines before it is built
+    interrupts: InterruptController,
+}
+
+impl MachineConfig {
+    // Starts a machine with dense RAM of ram_size bytes, the stack at the top of the RAM and the entry at 0
+    pub fn new(ram_size: usize) -> Self {
+        Self {
+            ram_size,
+            ram_kind: RamKind::Dense,
+            roms: Vec::new(),
+            devices: Vec::new(),
+            stack_pointer: None,
+            entry: 0,
+            interrupts: InterruptController::new(),
+        }
+    }
+
+    pub fn ram_kind(mut self, kind: RamKind) -> Self {
+        self.ram_kind = kind;
+        self
+    }
+
+    // Declares a ROM of size bytes at base flashed with image, it can be read and executed
+    pub fn rom(mut self, name: &str, base: u64, size: usize, image: &[u8]) -> Self {
+        self.roms.push(RomImage {
+            name: name.to_owned(),
+            base,
+            size,
+            image: image.to_vec(),
+        });
+        self
+    }
+
+    // Declares a device mapped at base that allows every access
+    pub fn device(self, name: &str, base: u64, device: Box<dyn Device>) -> Self {
+        self.device_with(name, base, device, Permissions::ALL)
+    }
+
+    // Declares a device mapped at base that allows only the given accesses
+    pub fn device_with(
+        mut self,
+        name: &str,
+        base: u64,
+        device: Box<dyn Device>,
+        permissions: Permissions,
+    ) -> Self {
+        self.devices.push(DeviceEntry {
+            name: name.to_owned(),
+            base,
+            device,
+            permissions,
+        });
+        self
+    }
+
+    // Sets the initial stack pointer, by default the last stack slot of the RAM
+    pub fn stack_pointer(mut self, sp: u64) -> Self {
+        self.stack_pointer = Some(sp);
+        self
+    }
+
+    // Sets the initial instruction pointer
+    pub fn entry(mut self, ip: u64) -> Self {
+        self.entry = ip;
+        self
+    }
+
+    // Returns an IRQ line of the machine to hand to a device, if there is a line with that number
+    pub fn irq_line(&self, irq: u8) -> Option<IrqLine> {
+        self.interrupts.line(irq)
+    }
+
+    // Checks the layout and creates a CPU with everything mapped, ready to run
+    pub fn build(self) -> Result<Cpu, ConfigError> {
+        let sp = match self.stack_pointer {
+            Some(sp) => sp,
+            None => (self.ram_size as u64)
+                .checked_sub(STACK_SLOT)
+                .ok_or(ConfigError::StackOutsideRam { sp: 0 })?,
+        };
+        self.check_layout(sp)?;
+
+        let mut cpu = Cpu::with_ram(self.ram_size, self.ram_kind);
+        cpu.dev_mapper.set_interrupts(self.interrupts);
+        for rom in self.roms {
+            let mut device = Rom::new(rom.size);
+            device.flash(&rom.image);
+            cpu.attach_with(
+                Box::new(device),
+                rom.name,
+                rom.base,
+                Permissions::READ | Permissions::EXECUTE,
+            );
+        }
+        for entry in self.devices {
+            cpu.attach_with(entry.device, entry.name, entry.base, entry.permissions);
+        }
+
+        cpu.set_reg(Register::StackPointer, sp);
+        cpu.set_reg(Register::InstructionPointer, self.entry);
+        Ok(cpu)
+    }
+
+    // Returns the name and the half-open address range of every ROM and device
+    fn regions(&self) -> Result<Vec<(&str, u64, u64)>, ConfigError> {
+        let roms = self.roms.iter().map(|rom| (&rom.name, rom.base, rom.size));
+        let devices = self
+            .devices
+            .iter()
+            .map(|entry| (&entry.name, entry.base, entry.device.size()));
+
+        roms.chain(devices)
+            .map(|(name, base, size)| {
+                let end = base
+                    .checked_add(size as u64)
+                    .ok_or_else(|| ConfigError::OutOfAddressSpace { name: name.clone() })?;
+                Ok((name.as_str(), base, end))
+            })
+            .collect()
+    }
+
+    // ROMs and devices may shadow RAM but not each other, nor the first stack slot, and each
+    // name is used by one device only
+    fn check_layout(&self, sp: u64) -> Result<(), ConfigError> {
+        for rom in &self.roms {
+            if rom.image.len() > rom.size {
+                return Err(ConfigError::ImageTooLarge {
+                    name: rom.name.clone(),
+                    size: rom.size,
+                    len: rom.image.len(),
+                });
+            }
+        }
+
+        let regions = self.regions()?;
+        let mut names: HashSet<&str> = HashSet::from([RAM_NAME]);
+        for (name, ..) in &regions {
+            if !names.insert(name) {
+                return Err(ConfigError::DuplicateName {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        for (i, (first, start, end)) in regions.iter().enumerate() {
+            let overlap = regions[i + 1..]
+                .iter()
+                .find(|(_, other_start, other_end)| start < other_end && other_start < end);
+            if let Some((second, ..)) = overlap {
+                return Err(ConfigError::Overlap {
+                    first: first.to_string(),
+                    second: second.to_string(),
+                });
+            }
+        }
+
+        let ram_end = self.ram_size as u64;
+        match sp.checked_add(STACK_SLOT) {
+            Some(slot_end) if slot_end <= ram_end => {}
+            _ => return Err(ConfigError::StackOutsideRam { sp }),
+        }
+        let shadow = regions
+            .iter()
+            .find(|(_, start, end)| *start < sp + STACK_SLOT && sp < *end);
+        if let Some((name, ..)) = shadow {
+            return Err(ConfigError::StackShadowed {
+                sp,
+                name: name.to_string(),
+            });
+        }
+
+        let ip = self.entry;
+        let mapped = ip < ram_end
+            || regions
+                .iter()
+                .any(|(_, start, end)| (*start..*end).contains(&ip));
+        if !mapped {
+            return Err(ConfigError::EntryUnmapped { ip });
+        }
+        Ok(())
+    }
+}
+
+// An invalid machine layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    // A ROM image is larger than the ROM
+    ImageTooLarge {
+        name: String,
+        size: usize,
+        len: usize,
+    },
+    // A device ends past the end of the address space
+    OutOfAddressSpace {
+        name: String,
+    },
+    // Two ROMs or devices, or one of them and the RAM, have the same name
+    DuplicateName {
+        name: String,
+    },
+    // Two ROMs or devices are mapped over each other
+    Overlap {
+        first: String,
+        second: String,
+    },
+    // The first stack slot is not inside the RAM
+    StackOutsideRam {
+        sp: u64,
+    },
+    // The first stack slot is inside the RAM but a device is mapped over it
+    StackShadowed {
+        sp: u64,
+        name: String,
+    },
+    // Nothing is mapped at the entry
+    EntryUnmapped {
+        ip: u64,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ConfigError::*;
+        match self {
+            ImageTooLarge { name, size, len } => {
+                write!(
+                    f,
+                    "image of {} bytes does not fit in {} of {} bytes",
+                    len, name, size
+                )
+            }
+            OutOfAddressSpace { name } => write!(f, "{} ends past the address space", name),
+            DuplicateName { name } => write!(f, "more than one device is named {}", name),
+            Overlap { first, second } => write!(f, "{} overlaps {}", first, second),
+            StackOutsideRam { sp } => write!(f, "stack pointer {:#x} is outside RAM", sp),
+            StackShadowed { sp, name } => {
+                write!(f, "stack pointer {:#x} is shadowed by {}", sp, name)
+            }
+            EntryUnmapped { ip } => write!(f, "nothing is mapped at the entry {:#x}", ip),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
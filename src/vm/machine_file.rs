@@ -0,0 +1,396 @@
+use std::{
+    cell::RefCell,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use hashbrown::{HashMap, HashSet};
+use toml::{Table, Value};
+
+use crate::{
+    dev_map::{
+        device::Device,
+        interrupts::{IrqLine, IRQ_LINES},
+        permissions::Permissions,
+    },
+    devices::{ram::Ram, rom::Rom, sparse_ram::SparseRam, stdout::Stdout, RamKind},
+    machine::{ConfigError, MachineConfig},
+    Cpu,
+};
+
+// Creates a device from its description in a machine file and the IRQ line of its irq field
+pub type DeviceFactory =
+    Box<dyn Fn(&DeviceSpec, Option<IrqLine>) -> Result<Box<dyn Device>, MachineFileError>>;
+
+// A device type of the registry, with the accesses its devices allow unless the machine file sets them
+struct DeviceType {
+    factory: DeviceFactory,
+    permissions: Permissions,
+}
+
+// The device types a machine file can use, by the name in their type field
+pub struct DeviceRegistry {
+    types: HashMap<String, DeviceType>,
+}
+
+impl DeviceRegistry {
+    // Creates a registry without any device type
+    pub fn empty() -> Self {
+        Self {
+            types: HashMap::new(),
+        }
+    }
+
+    // Adds a device type whose devices allow every access, replacing the one with the same name
+    pub fn register(&mut self, kind: &str, factory: DeviceFactory) {
+        self.register_with(kind, Permissions::ALL, factory);
+    }
+
+    // Adds a device type whose devices allow only the given accesses unless the machine file sets them
+    pub fn register_with(&mut self, kind: &str, permissions: Permissions, factory: DeviceFactory) {
+        self.types.insert(
+            kind.to_owned(),
+            DeviceType {
+                factory,
+                permissions,
+            },
+        );
+    }
+
+    // Returns the accesses the devices of a type allow by default
+    pub fn permissions(&self, kind: &str) -> Option<Permissions> {
+        self.types.get(kind).map(|kind| kind.permissions)
+    }
+
+    // Creates the device a spec describes, connected to an IRQ line if it has one. An option the
+    // factory did not read is not an option of the device type and is rejected like a typo
+    pub fn create(
+        &self,
+        spec: &DeviceSpec,
+        irq_line: Option<IrqLine>,
+    ) -> Result<Box<dyn Device>, MachineFileError> {
+        let kind = self
+            .types
+            .get(&spec.kind)
+            .ok_or_else(|| MachineFileError::UnknownDeviceType(spec.kind.clone()))?;
+        spec.read.borrow_mut().clear();
+        let device = (kind.factory)(spec, irq_line)?;
+        match spec.unread() {
+            Some(key) => Err(spec.invalid(key)),
+            None => Ok(device),
+        }
+    }
+}
+
+// The registry with the devices of the crate: ram, sparse_ram, rom and stdout. ROMs can be read and
+// executed like the ones of MachineConfig::rom
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(
+            "ram",
+            Box::new(|spec, _| Ok(Box::new(Ram::new(spec.size()?)))),
+        );
+        registry.register(
+            "sparse_ram",
+            Box::new(|spec, _| Ok(Box::new(SparseRam::new(spec.size()?)))),
+        );
+        registry.register("stdout", Box::new(|_, _| Ok(Box::new(Stdout::new()))));
+        registry.register_with(
+            "rom",
+            Permissions::READ | Permissions::EXECUTE,
+            Box::new(|spec, _| rom(spec)),
+        );
+        registry
+    }
+}
+
+// Creates a ROM of the given size flashed with the optional image file
+fn rom(spec: &DeviceSpec) -> Result<Box<dyn Device>, MachineFileError> {
+    let size = spec.size()?;
+    let image = match spec.path("image")? {
+        Some(path) => std::fs::read(path)?,
+        None => Vec::new(),
+    };
+    if image.len() > size {
+        return Err(MachineFileError::Config(ConfigError::ImageTooLarge {
+            name: spec.name.clone(),
+            size,
+            len: image.len(),
+        }));
+    }
+
+    let mut rom = Rom::new(size);
+    rom.flash(&image);
+    Ok(Box::new(rom))
+}
+
+// A device in a machine file, the fields besides type, name, base, permissions and irq are options of the device type
+pub struct DeviceSpec {
+    pub kind: String,
+    pub name: String,
+    pub base: u64,
+    // The accesses the device allows, the default of the device type when the file does not set them
+    pub permissions: Option<Permissions>,
+    pub irq: Option<u8>,
+    options: Table,
+    // The options the factory has read, the others are left over
+    read: RefCell<HashSet<String>>,
+    // The directory of the machine file, paths in options are relative to it
+    dir: PathBuf,
+}
+
+impl DeviceSpec {
+    // Returns the required size option
+    pub fn size(&self) -> Result<usize, MachineFileError> {
+        self.integer("size")?
+            .map(|size| size as usize)
+            .ok_or_else(|| self.invalid("size"))
+    }
+
+    // Returns an option that must be a non-negative integer
+    pub fn integer(&self, key: &str) -> Result<Option<u64>, MachineFileError> {
+        match self.option(key) {
+            None => Ok(None),
+            Some(value) => integer(value).map(Some).ok_or_else(|| self.invalid(key)),
+        }
+    }
+
+    // Returns an option that must be a string
+    pub fn string(&self, key: &str) -> Result<Option<&str>, MachineFileError> {
+        match self.option(key) {
+            None => Ok(None),
+            Some(value) => value.as_str().map(Some).ok_or_else(|| self.invalid(key)),
+        }
+    }
+
+    // Returns a path option resolved against the directory of the machine file
+    pub fn path(&self, key: &str) -> Result<Option<PathBuf>, MachineFileError> {
+        Ok(self.string(key)?.map(|path| self.dir.join(path)))
+    }
+
+    // Returns an option and marks it as read
+    fn option(&self, key: &str) -> Option<&Value> {
+        self.read.borrow_mut().insert(key.to_owned());
+        self.options.get(key)
+    }
+
+    // Returns the first option that has not been read
+    fn unread(&self) -> Option<&str> {
+        let read = self.read.borrow();
+        self.options
+            .keys()
+            .find(|key| !read.contains(key.as_str()))
+            .map(String::as_str)
+    }
+
+    fn invalid(&self, key: &str) -> MachineFileError {
+        MachineFileError::InvalidField(format!("device.{}.{}", self.name, key))
+    }
+}
+
+// A machine description: the RAM, the entry and stack pointers and the devices mapped over the RAM
+//
+// memory = 0x100000
+// ram = "sparse"
+// entry = 0
+//
+// [[device]]
+// type = "rom"
+// base = 0
+// size = 0x10000
+// image = "hello.bin"
+pub struct MachineFile {
+    pub memory: usize,
+    pub ram: RamKind,
+    pub entry: u64,
+    pub stack: Option<u64>,
+    pub devices: Vec<DeviceSpec>,
+}
+
+impl MachineFile {
+    // Reads a machine file, the paths in it are relative to its directory
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MachineFileError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text, path.parent().unwrap_or(Path::new("")))
+    }
+
+    // Parses a machine file whose paths are relative to dir
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, MachineFileError> {
+        let mut table: Table = text
+            .parse()
+            .map_err(|err: toml::de::Error| MachineFileError::Parse(err.message().to_owned()))?;
+
+        let invalid = |field: &str| MachineFileError::InvalidField(field.to_owned());
+        let memory = table
+            .get("memory")
+            .and_then(integer)
+            .ok_or_else(|| invalid("memory"))? as usize;
+        let ram = match table.get("ram").map(Value::as_str) {
+            None | Some(Some("dense")) => RamKind::Dense,
+            Some(Some("sparse")) => RamKind::Sparse,
+            Some(_) => return Err(invalid("ram")),
+        };
+        let entry = match table.get("entry") {
+            None => 0,
+            Some(value) => integer(value).ok_or_else(|| invalid("entry"))?,
+        };
+        let stack = match table.get("stack") {
+            None => None,
+            Some(value) => Some(integer(value).ok_or_else(|| invalid("stack"))?),
+        };
+
+        let devices = match table.remove("device") {
+            None => Vec::new(),
+            Some(Value::Array(devices)) => devices
+                .into_iter()
+                .map(|device| Self::device(device, dir))
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(invalid("device")),
+        };
+        if let Some(key) = table.keys().find(|key| !FIELDS.contains(&key.as_str())) {
+            return Err(invalid(key));
+        }
+
+        Ok(Self {
+            memory,
+            ram,
+            entry,
+            stack,
+            devices,
+        })
+    }
+
+    // Parses a device table, the name defaults to the type
+    fn device(device: Value, dir: &Path) -> Result<DeviceSpec, MachineFileError> {
+        let Value::Table(mut options) = device else {
+            return Err(MachineFileError::InvalidField(String::from("device")));
+        };
+
+        let kind = match options.remove("type") {
+            Some(Value::String(kind)) => kind,
+            _ => return Err(MachineFileError::InvalidField(String::from("device.type"))),
+        };
+        let name = match options.remove("name") {
+            None => kind.clone(),
+            Some(Value::String(name)) => name,
+            Some(_) => {
+                return Err(MachineFileError::InvalidField(format!(
+                    "device.{}.name",
+                    kind
+                )))
+            }
+        };
+        let base = options
+            .remove("base")
+            .as_ref()
+            .and_then(integer)
+            .ok_or_else(|| MachineFileError::InvalidField(format!("device.{}.base", name)))?;
+        let permissions = match options.remove("permissions") {
+            None => None,
+            Some(Value::String(permissions)) => {
+                Some(Permissions::parse(&permissions).ok_or_else(|| {
+                    MachineFileError::InvalidField(format!("device.{}.permissions", name))
+                })?)
+            }
+            Some(_) => {
+                return Err(MachineFileError::InvalidField(format!(
+                    "device.{}.permissions",
+                    name
+                )))
+            }
+        };
+        let irq = match options.remove("irq") {
+            None => None,
+            Some(value) => Some(
+                integer(&value)
+                    .and_then(|irq| u8::try_from(irq).ok())
+                    .filter(|irq| *irq < IRQ_LINES)
+                    .ok_or_else(|| {
+                        MachineFileError::InvalidField(format!("device.{}.irq", name))
+                    })?,
+            ),
+        };
+
+        Ok(DeviceSpec {
+            kind,
+            name,
+            base,
+            permissions,
+            irq,
+            options,
+            read: RefCell::new(HashSet::new()),
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    // Creates the devices with the registry and builds the machine, the layout is checked like any MachineConfig
+    pub fn build(&self, registry: &DeviceRegistry) -> Result<Cpu, MachineFileError> {
+        let mut config = MachineConfig::new(self.memory)
+            .ram_kind(self.ram)
+            .entry(self.entry);
+        if let Some(sp) = self.stack {
+            config = config.stack_pointer(sp);
+        }
+        for spec in &self.devices {
+            let irq_line = spec.irq.and_then(|irq| config.irq_line(irq));
+            let device = registry.create(spec, irq_line)?;
+            let permissions = spec
+                .permissions
+                .or_else(|| registry.permissions(&spec.kind))
+                .unwrap_or(Permissions::ALL);
+            config = config.device_with(&spec.name, spec.base, device, permissions);
+        }
+        Ok(config.build()?)
+    }
+}
+
+// The top-level fields of a machine file besides the devices, others are rejected like a typo
+const FIELDS: [&str; 4] = ["memory", "ram", "entry", "stack"];
+
+// TOML integers are signed, addresses and sizes can not be negative
+fn integer(value: &Value) -> Option<u64> {
+    value
+        .as_integer()
+        .and_then(|value| u64::try_from(value).ok())
+}
+
+// An error reading a machine file or building the machine it describes
+#[derive(Debug)]
+pub enum MachineFileError {
+    Io(io::Error),
+    // The file is not valid TOML
+    Parse(String),
+    // A field is missing or has the wrong type, named like device.rom.size
+    InvalidField(String),
+    UnknownDeviceType(String),
+    Config(ConfigError),
+}
+
+impl fmt::Display for MachineFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use MachineFileError::*;
+        match self {
+            Io(err) => write!(f, "{}", err),
+            Parse(message) => write!(f, "invalid machine file: {}", message.trim_end()),
+            InvalidField(field) => write!(f, "missing or invalid field {}", field),
+            UnknownDeviceType(kind) => write!(f, "unknown device type {}", kind),
+            Config(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for MachineFileError {}
+
+impl From<io::Error> for MachineFileError {
+    fn from(err: io::Error) -> Self {
+        MachineFileError::Io(err)
+    }
+}
+
+impl From<ConfigError> for MachineFileError {
+    fn from(err: ConfigError) -> Self {
+        MachineFileError::Config(err)
+    }
+}
@@ -1,5 +1,15 @@
 pub mod cpu;
 pub mod dev_map;
 pub mod devices;
+pub mod error;
+pub mod gdb;
+pub mod machine;
+pub mod machine_file;
 pub mod opcodes;
 pub mod register;
+pub mod replay;
+pub mod snapshot;
+pub mod step;
+pub mod syscall;
+pub mod trace;
+pub mod vectors;
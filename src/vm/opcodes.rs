@@ -1,6 +1,8 @@
 use strum_macros::{EnumIter, EnumVariantNames};
 
-#[derive(Debug, EnumIter, EnumVariantNames)]
+use crate::register::Register;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, EnumIter, EnumVariantNames)]
 pub enum Opcode {
     // Misc
     Nop = 0xFF,
@@ -10,6 +12,18 @@ pub enum Opcode {
     Mov = 0x01,
     Lod = 0x02,
     Str = 0x03,
+    Movb = 0x04,
+    Movh = 0x05,
+    Movw = 0x06,
+    Lodb = 0x07,
+    Lodh = 0x08,
+    Lodw = 0x09,
+    Lodsb = 0x0A,
+    Lodsh = 0x0B,
+    Lodsw = 0x0C,
+    Strb = 0x0D,
+    Strh = 0x0E,
+    Strw = 0x0F,
 
     // Arithmetic
     Add = 0x11,
@@ -18,6 +32,11 @@ pub enum Opcode {
     Div = 0x14,
     Inc = 0x15,
     Dec = 0x16,
+    Cmp = 0x17,
+    Imul = 0x18,
+    Idiv = 0x19,
+    Mod = 0x1A,
+    Imod = 0x1B,
 
     // Bitwise
     And = 0x21,
@@ -26,6 +45,8 @@ pub enum Opcode {
     Not = 0x24,
     Shl = 0x25,
     Shr = 0x26,
+    Tst = 0x27,
+    Sar = 0x28,
 
     // Branching
     Jmp = 0x31,
@@ -37,6 +58,10 @@ pub enum Opcode {
     Jle = 0x37,
     Jnz = 0x38,
     Jz = 0x39,
+    Jsgt = 0x3A,
+    Jslt = 0x3B,
+    Jsge = 0x3C,
+    Jsle = 0x3D,
 
     // Stack
     Psh = 0x41,
@@ -46,12 +71,21 @@ pub enum Opcode {
     Clr = 0x45,
     Ret = 0x46,
     Cal = 0x47,
+
+    // Interrupts
+    Int = 0x51,
+    Iret = 0x52,
+    Cli = 0x53,
+    Sti = 0x54,
+    Sys = 0x55,
 }
 
-impl From<u8> for Opcode {
-    fn from(opcode: u8) -> Self {
+impl TryFrom<u8> for Opcode {
+    type Error = u8;
+
+    fn try_from(opcode: u8) -> Result<Self, Self::Error> {
         use Opcode::*;
-        match opcode {
+        Ok(match opcode {
             // Misc
             0xFF => Nop,
 
@@ -59,6 +93,18 @@ impl From<u8> for Opcode {
             0x01 => Mov,
             0x02 => Lod,
             0x03 => Str,
+            0x04 => Movb,
+            0x05 => Movh,
+            0x06 => Movw,
+            0x07 => Lodb,
+            0x08 => Lodh,
+            0x09 => Lodw,
+            0x0A => Lodsb,
+            0x0B => Lodsh,
+            0x0C => Lodsw,
+            0x0D => Strb,
+            0x0E => Strh,
+            0x0F => Strw,
 
             // Arithmetic
             0x11 => Add,
@@ -67,6 +113,11 @@ impl From<u8> for Opcode {
             0x14 => Div,
             0x15 => Inc,
             0x16 => Dec,
+            0x17 => Cmp,
+            0x18 => Imul,
+            0x19 => Idiv,
+            0x1A => Mod,
+            0x1B => Imod,
 
             // Bitwise
             0x21 => And,
@@ -75,6 +126,8 @@ impl From<u8> for Opcode {
             0x24 => Not,
             0x25 => Shl,
             0x26 => Shr,
+            0x27 => Tst,
+            0x28 => Sar,
 
             // Branching
             0x31 => Jmp,
@@ -86,6 +139,10 @@ impl From<u8> for Opcode {
             0x37 => Jle,
             0x38 => Jnz,
             0x39 => Jz,
+            0x3A => Jsgt,
+            0x3B => Jslt,
+            0x3C => Jsge,
+            0x3D => Jsle,
 
             // Stack
             0x41 => Psh,
@@ -95,14 +152,82 @@ impl From<u8> for Opcode {
             0x45 => Clr,
             0x46 => Ret,
             0x47 => Cal,
+            0x51 => Int,
+            0x52 => Iret,
+            0x53 => Cli,
+            0x54 => Sti,
+            0x55 => Sys,
             0xFE => Hlt,
 
-            _ => panic!("Invalid opcode: {0:#x}", opcode),
+            _ => return Err(opcode),
+        })
+    }
+}
+
+impl Opcode {
+    // Returns how many bytes a load or store moves between registers and memory
+    pub fn width(&self) -> Width {
+        use Opcode::*;
+        match self {
+            Movb | Lodb | Lodsb | Strb => Width::Byte,
+            Movh | Lodh | Lodsh | Strh => Width::Half,
+            Movw | Lodw | Lodsw | Strw => Width::Word,
+            _ => Width::Double,
+        }
+    }
+
+    // Returns the group of the opcode, which is the high nibble of its encoding
+    pub fn class(&self) -> OpcodeClass {
+        match *self as u8 >> 4 {
+            0x0 => OpcodeClass::LoadStore,
+            0x1 => OpcodeClass::Arithmetic,
+            0x2 => OpcodeClass::Bitwise,
+            0x3 => OpcodeClass::Branch,
+            0x4 => OpcodeClass::Stack,
+            0x5 => OpcodeClass::Interrupt,
+            _ => OpcodeClass::Misc,
+        }
+    }
+}
+
+// A group of related opcodes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OpcodeClass {
+    Misc,
+    LoadStore,
+    Arithmetic,
+    Bitwise,
+    Branch,
+    Stack,
+    Interrupt,
+}
+
+// The size of a value moved by a load or store
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Width {
+    Byte = 1,
+    Half = 2,
+    Word = 4,
+    Double = 8,
+}
+
+impl Width {
+    // Returns the number of bytes
+    pub fn bytes(&self) -> usize {
+        *self as usize
+    }
+
+    // Truncates a value to this width and zero or sign extends it back to 64 bits
+    pub fn extend(&self, value: u64, signed: bool) -> u64 {
+        let unused = 64 - self.bytes() as u32 * 8;
+        match signed {
+            true => (((value << unused) as i64) >> unused) as u64,
+            false => (value << unused) >> unused,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AddrMode {
     ImmToReg = 0x10,
     ImmToMem = 0x20,
@@ -114,12 +239,35 @@ pub enum AddrMode {
     Register = 0x80,
     Memory = 0x90,
     Null = 0xA0,
+
+    // Register indirect, [reg]
+    IndToReg = 0xB0,
+    RegToInd = 0xB1,
+    ImmToInd = 0xB2,
+
+    // Base and displacement, [reg+disp]
+    DispToReg = 0xC0,
+    RegToDisp = 0xC1,
+    ImmToDisp = 0xC2,
+
+    // Base and scaled index, [base+index*scale]
+    IdxToReg = 0xD0,
+    RegToIdx = 0xD1,
+    ImmToIdx = 0xD2,
+
+    // Relative to the next instruction, rel and [ip+disp]
+    Relative = 0xE0,
+    RelToReg = 0xE1,
+    RegToRel = 0xE2,
+    ImmToRel = 0xE3,
 }
 
-impl From<u8> for AddrMode {
-    fn from(addr_mode: u8) -> Self {
+impl TryFrom<u8> for AddrMode {
+    type Error = u8;
+
+    fn try_from(addr_mode: u8) -> Result<Self, Self::Error> {
         use AddrMode::*;
-        match addr_mode {
+        Ok(match addr_mode {
             0x10 => ImmToReg,
             0x20 => ImmToMem,
             0x30 => RegToReg,
@@ -130,48 +278,86 @@ impl From<u8> for AddrMode {
             0x80 => Register,
             0x90 => Memory,
             0xA0 => Null,
+            0xB0 => IndToReg,
+            0xB1 => RegToInd,
+            0xB2 => ImmToInd,
+            0xC0 => DispToReg,
+            0xC1 => RegToDisp,
+            0xC2 => ImmToDisp,
+            0xD0 => IdxToReg,
+            0xD1 => RegToIdx,
+            0xD2 => ImmToIdx,
+            0xE0 => Relative,
+            0xE1 => RelToReg,
+            0xE2 => RegToRel,
+            0xE3 => ImmToRel,
 
-            _ => panic!("Invalid address mode: {0:#x}", addr_mode),
-        }
+            _ => return Err(addr_mode),
+        })
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum Operand {
     Null,
     Reg(u8),
     Imm(u64),
     Mem(u64),
+    // Memory at the address in a register
+    Ind(u8),
+    // Memory at the address in a register plus a signed displacement
+    Disp(u8, i64),
+    // Memory at the address in a base register plus an index register times a scale
+    Idx(u8, u8, u8),
+    // Address relative to the next instruction
+    Rel(i64),
+}
+
+// Formats a register by its code
+fn fmt_reg(f: &mut std::fmt::Formatter, reg: u8) -> std::fmt::Result {
+    match Register::try_from(reg) {
+        Ok(reg) => write!(f, "{}", reg.mnemonic()),
+        Err(_) => write!(f, "Reg({0:#x})", reg),
+    }
 }
 
 impl std::fmt::Debug for Operand {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Operand::Null => write!(f, "Null"),
-            Operand::Reg(reg) => match reg {
-                1 => write!(f, "Acc"),
-                2 => write!(f, "Ip"),
-                3 => write!(f, "Sp"),
-                4 => write!(f, "Fp"),
-                5 => write!(f, "Fs"),
-                6 => write!(f, "Ac"),
-                7 => write!(f, "R1"),
-                8 => write!(f, "R2"),
-                9 => write!(f, "R3"),
-                10 => write!(f, "R4"),
-                11 => write!(f, "R5"),
-                12 => write!(f, "R6"),
-                13 => write!(f, "R7"),
-                14 => write!(f, "R8"),
-                0 | 15..=u8::MAX => panic!("Invalid register: {0:#x}", reg),
-            },
+            Operand::Reg(reg) => fmt_reg(f, *reg),
             // Prefix with #x to indicate literal
             Operand::Imm(imm) => write!(f, "#{:#x}", imm),
             Operand::Mem(mem) => write!(f, "[{:#x}]", mem),
+            Operand::Ind(reg) => {
+                write!(f, "[")?;
+                fmt_reg(f, *reg)?;
+                write!(f, "]")
+            }
+            Operand::Disp(reg, disp) => {
+                write!(f, "[")?;
+                fmt_reg(f, *reg)?;
+                match disp {
+                    0.. => write!(f, "+{:#x}]", disp),
+                    _ => write!(f, "-{:#x}]", disp.unsigned_abs()),
+                }
+            }
+            Operand::Rel(rel) => match rel {
+                0.. => write!(f, "$+{:#x}", rel),
+                _ => write!(f, "$-{:#x}", rel.unsigned_abs()),
+            },
+            Operand::Idx(base, index, scale) => {
+                write!(f, "[")?;
+                fmt_reg(f, *base)?;
+                write!(f, "+")?;
+                fmt_reg(f, *index)?;
+                write!(f, "*{}]", scale)
+            }
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub addr_mode: AddrMode,
@@ -206,10 +392,10 @@ impl std::fmt::Debug for Instruction {
             return write!(f, "{:?}", self.opcode);
         }
 
-        return write!(
+        write!(
             f,
             "{:?} {:?}: {:?}, {:?}",
             self.opcode, self.addr_mode, self.operands.0, self.operands.1
-        );
+        )
     }
 }
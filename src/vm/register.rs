@@ -3,13 +3,14 @@
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, EnumIter)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, EnumIter)]
 pub enum Register {
     Accumulator = 0x01,        // Accumulator
     InstructionPointer = 0x02, // Instruction pointer
     StackPointer = 0x03,       // Stack pointer
     FramePointer = 0x04,       // Frame pointer
     FrameSize = 0x05,          // Frame size
+    Flags = 0x06,              // Status flags
     Reg0 = 0x07,               // General purpose register
     Reg1 = 0x08,               // General purpose register
     Reg2 = 0x09,               // General purpose register
@@ -18,6 +19,24 @@ pub enum Register {
     Reg5 = 0x0C,               // General purpose register
     Reg6 = 0x0D,               // General purpose register
     Reg7 = 0x0E,               // General purpose register
+    VectorTable = 0x0F,        // Vector table address
+}
+
+// Bits of the flags register
+pub mod flags {
+    // The result was zero
+    pub const ZERO: u64 = 1 << 0;
+    // The unsigned result carried out of or borrowed into the highest bit
+    pub const CARRY: u64 = 1 << 1;
+    // The signed result did not fit in 64 bits
+    pub const OVERFLOW: u64 = 1 << 2;
+    // The highest bit of the result was set
+    pub const SIGN: u64 = 1 << 3;
+    // Interrupts raised by devices are taken, set by STI and cleared by CLI
+    pub const INTERRUPT: u64 = 1 << 4;
+
+    // All the flags set by arithmetic and compare instructions
+    pub const ARITHMETIC: u64 = ZERO | CARRY | OVERFLOW | SIGN;
 }
 
 impl Register {
@@ -30,28 +49,67 @@ impl Register {
 
         registers
     }
-}
 
-// TODO: Use strum
-impl ToString for Register {
-    fn to_string(&self) -> String {
+    // Returns the short name of the register used in the docs and by the tools
+    pub fn mnemonic(&self) -> &'static str {
         use Register::*;
         match self {
-            Accumulator => "accumulator".to_string(),
-            InstructionPointer => "instruction_pointer".to_string(),
-            StackPointer => "stack_pointer".to_string(),
-            FramePointer => "frame_pointer".to_string(),
-            FrameSize => "frame_size".to_string(),
-            Reg0 => "reg_0".to_string(),
-            Reg1 => "reg_1".to_string(),
-            Reg2 => "reg_2".to_string(),
-            Reg3 => "reg_3".to_string(),
-            Reg4 => "reg_4".to_string(),
-            Reg5 => "reg_5".to_string(),
-            Reg6 => "reg_6".to_string(),
-            Reg7 => "reg_7".to_string(),
+            Accumulator => "acc",
+            InstructionPointer => "ip",
+            StackPointer => "sp",
+            FramePointer => "fp",
+            FrameSize => "fs",
+            Flags => "flags",
+            Reg0 => "r0",
+            Reg1 => "r1",
+            Reg2 => "r2",
+            Reg3 => "r3",
+            Reg4 => "r4",
+            Reg5 => "r5",
+            Reg6 => "r6",
+            Reg7 => "r7",
+            VectorTable => "vt",
         }
     }
+
+    // Finds a register by its short name, ignoring case
+    pub fn from_mnemonic(name: &str) -> Option<Self> {
+        Register::iter().find(|reg| reg.mnemonic().eq_ignore_ascii_case(name))
+    }
+}
+
+// TODO: Use strum
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Register::*;
+        let name = match self {
+            Accumulator => "accumulator",
+            InstructionPointer => "instruction_pointer",
+            StackPointer => "stack_pointer",
+            FramePointer => "frame_pointer",
+            FrameSize => "frame_size",
+            Flags => "flags",
+            Reg0 => "reg_0",
+            Reg1 => "reg_1",
+            Reg2 => "reg_2",
+            Reg3 => "reg_3",
+            Reg4 => "reg_4",
+            Reg5 => "reg_5",
+            Reg6 => "reg_6",
+            Reg7 => "reg_7",
+            VectorTable => "vector_table",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Decodes a register from its code in the bytecode
+impl TryFrom<u8> for Register {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<Self, Self::Error> {
+        Register::iter().find(|reg| *reg as u8 == code).ok_or(code)
+    }
 }
 
 // TODO: Use strum
@@ -64,6 +122,7 @@ impl From<&str> for Register {
             "stack_pointer" => StackPointer,
             "frame_pointer" => FramePointer,
             "frame_size" => FrameSize,
+            "flags" => Flags,
             "reg_0" => Reg0,
             "reg_1" => Reg1,
             "reg_2" => Reg2,
@@ -72,7 +131,34 @@ impl From<&str> for Register {
             "reg_5" => Reg5,
             "reg_6" => Reg6,
             "reg_7" => Reg7,
+            "vector_table" => VectorTable,
             _ => panic!("Invalid register name: {}", name),
         }
     }
 }
+
+// A copy of the register file at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterSnapshot {
+    values: Vec<(Register, u64)>,
+}
+
+impl RegisterSnapshot {
+    pub(crate) fn new(values: Vec<(Register, u64)>) -> Self {
+        Self { values }
+    }
+
+    // Returns the value a register had when the snapshot was taken
+    pub fn get(&self, reg: Register) -> u64 {
+        self.values
+            .iter()
+            .find(|(name, _)| *name == reg)
+            .map(|(_, value)| *value)
+            .unwrap_or_default()
+    }
+
+    // Iterates over all registers and their values
+    pub fn iter(&self) -> impl Iterator<Item = (Register, u64)> + '_ {
+        self.values.iter().copied()
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{
+    dev_map::bus_fault::BusFault,
+    register::{Register, RegisterSnapshot},
+};
+
+// How many instructions apart the checkpoints reverse execution starts replaying from are by default
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1024;
+
+// A syscall made while recording and what its handler did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallRecord {
+    // The instruction of the run that made the syscall
+    pub step: u64,
+    // All the registers after the handler returned
+    pub regs: Vec<(Register, u64)>,
+    // The memory the handler wrote
+    pub writes: Vec<(u64, Vec<u8>)>,
+    pub result: Result<(), BusFault>,
+}
+
+// The nondeterministic inputs of a recorded run. Replaying them on a machine in the state the
+// recording started from executes the run again to the same state
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayLog {
+    // The number of instructions recorded
+    pub steps: u64,
+    // The results of reading devices that are not memory, in order
+    pub reads: Vec<Result<u64, BusFault>>,
+    // The instructions interrupts were taken before, with their IRQ
+    pub interrupts: Vec<(u64, u8)>,
+    pub syscalls: Vec<SyscallRecord>,
+}
+
+// The state of the CPU at an instruction of the run, memory is restored by undoing the journaled writes
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpoint {
+    pub(crate) step: u64,
+    pub(crate) regs: RegisterSnapshot,
+    pub(crate) running: bool,
+    pub(crate) cycles: u64,
+    pub(crate) reads: usize,
+    pub(crate) journal: usize,
+}
+
+// Records a run while the CPU executes it, and replays it where it was recorded before
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    // The instruction of the run the CPU is at
+    pub(crate) step: u64,
+    // The number of instructions recorded, replaying stops there
+    pub(crate) recorded: u64,
+    pub(crate) interval: u64,
+    pub(crate) interrupts: Vec<(u64, u8)>,
+    pub(crate) syscalls: Vec<SyscallRecord>,
+    pub(crate) checkpoints: Vec<Checkpoint>,
+}
+
+impl Recorder {
+    pub(crate) fn new(log: ReplayLog, interval: u64) -> Self {
+        Self {
+            step: 0,
+            recorded: log.steps,
+            interval: interval.max(1),
+            interrupts: log.interrupts,
+            syscalls: log.syscalls,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    // Checks if the CPU is at an instruction that was recorded before
+    pub(crate) fn replaying(&self) -> bool {
+        self.step < self.recorded
+    }
+
+    // Checks if a checkpoint is due at the current instruction
+    pub(crate) fn checkpoint_due(&self) -> bool {
+        self.step.is_multiple_of(self.interval)
+            && self
+                .checkpoints
+                .last()
+                .is_none_or(|checkpoint| checkpoint.step < self.step)
+    }
+
+    // Returns the latest checkpoint at or before an instruction
+    pub(crate) fn checkpoint_before(&self, step: u64) -> Option<&Checkpoint> {
+        let after = self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.step <= step);
+        after.checked_sub(1).map(|i| &self.checkpoints[i])
+    }
+
+    // Returns the interrupt recorded before the current instruction
+    pub(crate) fn interrupt(&self) -> Option<u8> {
+        self.interrupts
+            .binary_search_by_key(&self.step, |(step, _)| *step)
+            .ok()
+            .map(|i| self.interrupts[i].1)
+    }
+
+    // Records an interrupt taken before the current instruction
+    pub(crate) fn record_interrupt(&mut self, irq: u8) {
+        let step = self.step;
+        self.interrupts.retain(|(at, _)| *at < step);
+        self.interrupts.push((step, irq));
+    }
+
+    // Returns the syscall recorded at the current instruction
+    pub(crate) fn syscall(&self) -> Option<&SyscallRecord> {
+        self.syscalls
+            .binary_search_by_key(&self.step, |syscall| syscall.step)
+            .ok()
+            .map(|i| &self.syscalls[i])
+    }
+
+    // Records a syscall made by the current instruction
+    pub(crate) fn record_syscall(&mut self, syscall: SyscallRecord) {
+        self.syscalls
+            .retain(|recorded| recorded.step < syscall.step);
+        self.syscalls.push(syscall);
+    }
+
+    // Moves past an executed instruction, recording it if it was not recorded before
+    pub(crate) fn advance(&mut self) {
+        self.step += 1;
+        self.recorded = self.recorded.max(self.step);
+    }
+
+    // Returns the log of the run, with the reads recorded by the bus
+    pub(crate) fn into_log(self, reads: Vec<Result<u64, BusFault>>) -> ReplayLog {
+        ReplayLog {
+            steps: self.recorded,
+            reads,
+            interrupts: self.interrupts,
+            syscalls: self.syscalls,
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::{fmt, io, path::Path};
+
+use crate::register::Register;
+
+// Magic bytes and version at the start of a snapshot file
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"SLSS";
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+// The size of the pages memory is saved in, only pages that are not all zero are stored
+pub const SNAPSHOT_PAGE_SIZE: usize = 4096;
+
+// The state of a device that can be saved and loaded back, devices without state keep the defaults
+pub trait Snapshot {
+    // Saves the state of the device to bytes
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    // Loads a state saved by a device of the same type and size
+    fn load(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        match state.is_empty() {
+            true => Ok(()),
+            false => Err(SnapshotError::InvalidState),
+        }
+    }
+}
+
+// An error reading, decoding or restoring a snapshot
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    // The data does not start with the magic bytes
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    // The data ends in the middle of the snapshot
+    Truncated,
+    InvalidRegister(u8),
+    // The snapshot has a device the machine does not have mapped at the same address
+    MissingDevice { name: String, start: u64 },
+    // A device rejected its saved state
+    InvalidState,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SnapshotError::*;
+        match self {
+            Io(err) => write!(f, "{}", err),
+            InvalidMagic => write!(f, "not a snapshot"),
+            UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            Truncated => write!(f, "truncated snapshot"),
+            InvalidRegister(index) => write!(f, "invalid register {:#x} in snapshot", index),
+            MissingDevice { name, start } => {
+                write!(f, "no device {} mapped at {:#x}", name, start)
+            }
+            InvalidState => write!(f, "invalid device state"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+// The saved state of a mapped device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceState {
+    pub name: String,
+    pub start: u64,
+    pub state: Vec<u8>,
+}
+
+// A saved state of the whole machine: the registers, whether the CPU is running,
+// the pending interrupt requests, the cycle counter and the state of every mapped device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub regs: Vec<(Register, u64)>,
+    pub running: bool,
+    pub interrupts: u64,
+    pub cycles: u64,
+    pub devices: Vec<DeviceState>,
+}
+
+impl SaveState {
+    // Encodes the save state in little endian as the magic bytes, the version (2 bytes), the running flag,
+    // the pending interrupts (8 bytes), the cycle counter (8 bytes), the number of registers (2 bytes)
+    // followed by each code and value (1 + 8 bytes), and the number of devices (4 bytes) followed by
+    // each name length (2 bytes), name, start address (8 bytes), state length (8 bytes) and state
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        bytes.extend(SNAPSHOT_VERSION.to_le_bytes());
+        bytes.push(self.running as u8);
+        bytes.extend(self.interrupts.to_le_bytes());
+        bytes.extend(self.cycles.to_le_bytes());
+
+        bytes.extend((self.regs.len() as u16).to_le_bytes());
+        for (reg, value) in self.regs.iter() {
+            bytes.push(*reg as u8);
+            bytes.extend(value.to_le_bytes());
+        }
+
+        bytes.extend((self.devices.len() as u32).to_le_bytes());
+        for device in self.devices.iter() {
+            bytes.extend((device.name.len() as u16).to_le_bytes());
+            bytes.extend(device.name.as_bytes());
+            bytes.extend(device.start.to_le_bytes());
+            bytes.extend((device.state.len() as u64).to_le_bytes());
+            bytes.extend(&device.state);
+        }
+        bytes
+    }
+
+    // Decodes a save state encoded by to_bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader(bytes);
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let running = reader.take(1)?[0] != 0;
+        let interrupts = u64::from_le_bytes(reader.array()?);
+        let cycles = u64::from_le_bytes(reader.array()?);
+
+        let count = u16::from_le_bytes(reader.array()?);
+        let mut regs = Vec::new();
+        for _ in 0..count {
+            let index = reader.take(1)?[0];
+            let reg = Register::try_from(index).map_err(SnapshotError::InvalidRegister)?;
+            regs.push((reg, u64::from_le_bytes(reader.array()?)));
+        }
+
+        let count = u32::from_le_bytes(reader.array()?);
+        let mut devices = Vec::new();
+        for _ in 0..count {
+            let len = u16::from_le_bytes(reader.array()?) as usize;
+            let name = String::from_utf8_lossy(reader.take(len)?).into_owned();
+            let start = u64::from_le_bytes(reader.array()?);
+            let len = u64::from_le_bytes(reader.array()?);
+            let len = usize::try_from(len).map_err(|_| SnapshotError::Truncated)?;
+            let state = reader.take(len)?.to_vec();
+            devices.push(DeviceState { name, start, state });
+        }
+
+        Ok(Self {
+            regs,
+            running,
+            interrupts,
+            cycles,
+            devices,
+        })
+    }
+
+    // Writes the save state to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        Ok(std::fs::write(path, self.to_bytes())?)
+    }
+
+    // Reads a save state from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+// Encodes memory as the pages that are not all zero, each as its index (8 bytes) and content.
+// Takes the pages with their index, every page but the last one of the memory is SNAPSHOT_PAGE_SIZE bytes
+pub fn save_pages<'a>(pages: impl Iterator<Item = (usize, &'a [u8])>) -> Vec<u8> {
+    let mut state = Vec::new();
+    for (index, page) in pages {
+        if page.iter().any(|byte| *byte != 0) {
+            state.extend((index as u64).to_le_bytes());
+            state.extend(page);
+        }
+    }
+    state
+}
+
+// Decodes the pages of a memory of size bytes encoded by save_pages, passing each one with its index to load
+pub fn load_pages(
+    state: &[u8],
+    size: usize,
+    mut load: impl FnMut(usize, &[u8]),
+) -> Result<(), SnapshotError> {
+    let mut reader = Reader(state);
+    while !reader.0.is_empty() {
+        let index = u64::from_le_bytes(reader.array()?) as usize;
+        let start = index
+            .checked_mul(SNAPSHOT_PAGE_SIZE)
+            .filter(|start| *start < size)
+            .ok_or(SnapshotError::InvalidState)?;
+        let len = SNAPSHOT_PAGE_SIZE.min(size - start);
+        load(index, reader.take(len)?);
+    }
+    Ok(())
+}
+
+// Encodes contiguous memory with save_pages
+pub fn save_memory(memory: &[u8]) -> Vec<u8> {
+    save_pages(memory.chunks(SNAPSHOT_PAGE_SIZE).enumerate())
+}
+
+// Decodes contiguous memory encoded by save_memory, the pages that are not in the state are zeroed
+pub fn load_memory(memory: &mut [u8], state: &[u8]) -> Result<(), SnapshotError> {
+    memory.fill(0);
+    let size = memory.len();
+    load_pages(state, size, |index, page| {
+        let start = index * SNAPSHOT_PAGE_SIZE;
+        memory[start..start + page.len()].copy_from_slice(page);
+    })
+}
+
+// Reads a snapshot front to back
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
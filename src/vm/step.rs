@@ -0,0 +1,89 @@
+use crate::{dev_map::bus_fault::AccessKind, opcodes::Instruction, syscall::CpuContext};
+
+// How an executed instruction changed the control flow
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flow {
+    // Continued with the next instruction
+    Next,
+    // Jumped to another address
+    Branch { target: u64 },
+    // Called a subroutine
+    Call { target: u64 },
+    // Returned from a subroutine
+    Return { target: u64 },
+    // Faulted or executed INT and entered the handler of a vector
+    Trap { vector: u64, target: u64 },
+    // Halted the CPU
+    Halt,
+}
+
+// The result of executing a single instruction
+#[derive(Debug, Clone)]
+pub struct StepOutcome {
+    // Address of the executed instruction
+    pub addr: u64,
+    // The decoded instruction
+    pub instr: Instruction,
+    // What the instruction did to the control flow
+    pub flow: Flow,
+    // Vector of the interrupt taken right before the instruction, which is the first one of its handler
+    // unless fetching that one faulted. An IRQ is reported here over the bus fault of the fetch
+    pub interrupt: Option<u64>,
+    // Address whose fetch faulted, the instruction is then the first one of the bus fault handler
+    pub fetch_fault: Option<u64>,
+}
+
+impl StepOutcome {
+    pub fn halted(&self) -> bool {
+        self.flow == Flow::Halt
+    }
+
+    pub fn branched(&self) -> bool {
+        matches!(self.flow, Flow::Branch { .. })
+    }
+
+    pub fn called(&self) -> bool {
+        matches!(self.flow, Flow::Call { .. })
+    }
+
+    pub fn returned(&self) -> bool {
+        matches!(self.flow, Flow::Return { .. })
+    }
+
+    pub fn interrupted(&self) -> bool {
+        self.interrupt.is_some()
+    }
+
+    pub fn fetch_faulted(&self) -> bool {
+        self.fetch_fault.is_some()
+    }
+}
+
+// A frame of a backtrace
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Frame {
+    // Address the frame executes or returns to
+    pub addr: u64,
+    // Frame pointer of the frame
+    pub frame_pointer: u64,
+}
+
+// Why the CPU stopped running
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    // A HLT instruction was executed
+    Halted,
+    // The requested number of instructions was executed
+    InstructionLimit,
+    // The predicate passed to `run_until` matched
+    Condition,
+    // The next instruction is at a breakpoint
+    Breakpoint { addr: u64 },
+    // The last instruction accessed a watched address
+    Watchpoint { addr: u64, access: AccessKind },
+    // Reverse execution reached the instruction the recording started at
+    RecordingStart,
+}
+
+// A condition on the registers and memory that has to hold for a breakpoint to stop
+pub type BreakCondition = Box<dyn FnMut(&mut CpuContext) -> bool>;
@@ -0,0 +1,53 @@
+use crate::{dev_map::bus_fault::BusFault, register::Register, Cpu};
+
+// A host function the guest calls with SYS, it gets the arguments from and returns its results in registers
+pub type SyscallHandler = Box<dyn FnMut(&mut CpuContext) -> Result<(), BusFault>>;
+
+// The view of the CPU syscall handlers and breakpoint conditions get, to read and write the registers and memory of the guest
+pub struct CpuContext<'a> {
+    cpu: &'a mut Cpu,
+    // The memory written through the context, to record what a syscall did
+    pub(crate) writes: Vec<(u64, Vec<u8>)>,
+}
+
+impl<'a> CpuContext<'a> {
+    pub(crate) fn new(cpu: &'a mut Cpu) -> Self {
+        Self {
+            cpu,
+            writes: Vec::new(),
+        }
+    }
+
+    // Read the value of a register
+    pub fn reg(&self, reg: Register) -> u64 {
+        self.cpu.reg(reg)
+    }
+
+    // Overwrite the value of a register
+    pub fn set_reg(&mut self, reg: Register, value: u64) {
+        self.cpu.set_reg(reg, value);
+    }
+
+    // Read len bytes of guest memory
+    pub fn read_mem(&mut self, addr: u64, len: usize) -> Result<Vec<u8>, BusFault> {
+        self.cpu.read_mem(addr, len)
+    }
+
+    // Write bytes to guest memory
+    pub fn write_mem(&mut self, addr: u64, data: &[u8]) -> Result<(), BusFault> {
+        self.writes.push((addr, data.to_vec()));
+        self.cpu.write_mem(addr, data)
+    }
+
+    // Read a 64-bit value of guest memory. Like read_mem it is not watched nor recorded, since a replay
+    // applies the results of the syscall instead of running it
+    pub fn read64(&mut self, addr: u64) -> Result<u64, BusFault> {
+        self.cpu.dev_mapper.peek64(addr)
+    }
+
+    // Write a 64-bit value to guest memory
+    pub fn write64(&mut self, addr: u64, data: u64) -> Result<(), BusFault> {
+        self.writes.push((addr, data.to_le_bytes().to_vec()));
+        self.cpu.dev_mapper.write64(addr, data)
+    }
+}
@@ -0,0 +1,1918 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    dev_map::{
+        bus_fault::{AccessKind, BusFault},
+        device::Device,
+        device_mapper::{DeviceMapper, RegionInfo},
+        interrupts::{IrqLine, IRQ_LINES},
+        permissions::Permissions,
+        watchpoint::WatchKind,
+    },
+    devices::{ram::Ram, rom::Rom, sparse_ram::SparseRam, RamKind},
+    gdb::{packet, Connection, GdbStub},
+    machine_file::{DeviceRegistry, MachineFile, MachineFileError},
+    opcodes::{AddrMode::*, Opcode, Opcode::*, OpcodeClass},
+    register::{flags, Register},
+    replay::{ReplayLog, DEFAULT_CHECKPOINT_INTERVAL},
+    snapshot::{SaveState, Snapshot, SnapshotError, SNAPSHOT_PAGE_SIZE},
+    trace::{TraceFormat, Tracer, BINARY_MAGIC},
+    vectors, ConfigError, Cpu, Flow, MachineConfig, StopReason, VmError,
+};
+
+const RAM_SIZE: usize = 0x1000;
+const ROM_SIZE: usize = 0x100;
+
+// Creates a CPU with RAM and the program flashed into ROM at address 0
+fn cpu(program: &[u8]) -> Cpu {
+    MachineConfig::new(RAM_SIZE)
+        .rom("rom", 0, ROM_SIZE, program)
+        .build()
+        .unwrap()
+}
+
+// Encodes an immediate or memory address operand
+fn imm(value: u64) -> [u8; 8] {
+    value.to_be_bytes()
+}
+
+#[test]
+fn test_invalid_opcode() {
+    let program = [Nop as u8, Null as u8, 0x00, Null as u8];
+
+    let err = cpu(&program).run().unwrap_err();
+    assert_eq!(err, VmError::InvalidOpcode { ip: 2, byte: 0x00 });
+}
+
+#[test]
+fn test_invalid_addr_mode() {
+    let program = [Nop as u8, 0x00];
+
+    let err = cpu(&program).run().unwrap_err();
+    assert_eq!(err, VmError::InvalidAddrMode { ip: 0, byte: 0x00 });
+}
+
+#[test]
+fn test_invalid_operands() {
+    let program = [Ret as u8, Literal as u8, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    let err = cpu(&program).run().unwrap_err();
+    assert_eq!(
+        err,
+        VmError::InvalidOperands {
+            ip: 0,
+            opcode: Opcode::Ret,
+            mode: Literal,
+        }
+    );
+}
+
+#[test]
+fn test_invalid_register() {
+    let program = [&[Mov as u8, ImmToReg as u8][..], &imm(1), &[0x00]].concat();
+
+    let err = cpu(&program).run().unwrap_err();
+    assert_eq!(err, VmError::InvalidRegister { ip: 0, index: 0x00 });
+}
+
+#[test]
+fn test_rom_write_is_bus_fault() {
+    let program = [&[Mov as u8, ImmToMem as u8][..], &imm(1), &imm(0x10)].concat();
+
+    let err = cpu(&program).run().unwrap_err();
+    assert_eq!(
+        err,
+        VmError::BusFault {
+            ip: 0,
+            addr: 0x10,
+            kind: AccessKind::Write,
+        }
+    );
+}
+
+#[test]
+fn test_divide_by_zero() {
+    let program = [
+        &[Div as u8, ImmToReg as u8][..],
+        &imm(0),
+        &[Register::Reg0 as u8],
+    ]
+    .concat();
+
+    let err = cpu(&program).run().unwrap_err();
+    assert_eq!(err, VmError::DivideByZero { ip: 0 });
+}
+
+#[test]
+fn test_step_outcome() {
+    let program = [
+        &[Nop as u8, Null as u8][..],
+        &[Jmp as u8, Literal as u8],
+        &imm(0x20),
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+
+    let outcome = cpu.step().unwrap();
+    assert_eq!(outcome.addr, 0);
+    assert_eq!(outcome.flow, Flow::Next);
+
+    let outcome = cpu.step().unwrap();
+    assert_eq!(outcome.addr, 2);
+    assert_eq!(outcome.instr.opcode, Opcode::Jmp);
+    assert_eq!(outcome.flow, Flow::Branch { target: 0x20 });
+}
+
+#[test]
+fn test_run_for_and_run_until() {
+    // Spin forever on a jump to itself
+    let program = [
+        &[Nop as u8, Null as u8][..],
+        &[Jmp as u8, Literal as u8],
+        &imm(0x00),
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+
+    assert_eq!(cpu.run_for(5).unwrap(), StopReason::InstructionLimit);
+    assert_eq!(
+        cpu.run_until(|outcome| outcome.branched()).unwrap(),
+        StopReason::Condition
+    );
+
+    // A halting program stops before the limit
+    let mut cpu = self::cpu(&[Hlt as u8, Null as u8]);
+    assert_eq!(cpu.run_for(5).unwrap(), StopReason::Halted);
+}
+
+#[test]
+fn test_register_and_memory_inspection() {
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(0x2A),
+        &[Register::Reg0 as u8],
+        &[Add as u8, ImmToReg as u8],
+        &imm(0x01),
+        &[Register::Reg0 as u8],
+        &[Mov as u8, RegToMem as u8, Register::Accumulator as u8],
+        &imm(0x800),
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+    cpu.set_reg(Register::Reg7, 0x07);
+    cpu.run().unwrap();
+
+    assert_eq!(cpu.reg(Register::Reg0), 0x2A);
+    assert_eq!(cpu.reg(Register::Accumulator), 0x2B);
+
+    let regs = cpu.registers();
+    assert_eq!(regs.get(Register::Reg7), 0x07);
+    assert_eq!(regs.iter().count(), Register::all().len());
+
+    assert_eq!(cpu.read_mem(0x800, 2).unwrap(), vec![0x2B, 0x00]);
+    cpu.write_mem(0x800, &[0xAA, 0xBB]).unwrap();
+    assert_eq!(cpu.read_mem(0x800, 2).unwrap(), vec![0xAA, 0xBB]);
+    assert!(cpu.write_mem(0x00, &[0x00]).is_err());
+}
+
+#[test]
+fn test_cmp_and_conditional_jumps() {
+    // Jump to 0x30 if R0 > 3, the jump target is independent of the compared value
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(5),
+        &[Register::Reg0 as u8],
+        &[Cmp as u8, ImmToReg as u8],
+        &imm(3),
+        &[Register::Reg0 as u8],
+        &[Jgt as u8, Literal as u8],
+        &imm(0x30),
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+    cpu.run_for(3).unwrap();
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0x30);
+    assert_eq!(cpu.reg(Register::Flags) & flags::ARITHMETIC, 0);
+
+    // Compare equal values and fall through a jlt
+    let program = [
+        &[
+            Cmp as u8,
+            RegToReg as u8,
+            Register::Reg0 as u8,
+            Register::Reg1 as u8,
+        ][..],
+        &[Jlt as u8, Literal as u8],
+        &imm(0x30),
+    ]
+    .concat();
+    let mut cpu = self::cpu(&program);
+    cpu.run_for(2).unwrap();
+    assert_eq!(cpu.reg(Register::InstructionPointer), 14);
+    assert_eq!(cpu.reg(Register::Flags), flags::ZERO);
+}
+
+#[test]
+fn test_arithmetic_flags() {
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(u64::MAX),
+        &[Register::Reg0 as u8],
+        &[Add as u8, ImmToReg as u8],
+        &imm(1),
+        &[Register::Reg0 as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+    cpu.run_for(2).unwrap();
+    assert_eq!(cpu.reg(Register::Accumulator), 0);
+    assert_eq!(cpu.reg(Register::Flags), flags::ZERO | flags::CARRY);
+
+    let program = [
+        Tst as u8,
+        RegToReg as u8,
+        Register::Reg0 as u8,
+        Register::Reg1 as u8,
+    ];
+    let mut cpu = self::cpu(&program);
+    cpu.set_reg(Register::Reg0, u64::MAX);
+    cpu.set_reg(Register::Reg1, 1 << 63);
+    cpu.step().unwrap();
+    assert_eq!(cpu.reg(Register::Flags), flags::SIGN);
+
+    // MUL sets CARRY on unsigned overflow and OVERFLOW on signed overflow
+    let program = [
+        Mul as u8,
+        RegToReg as u8,
+        Register::Reg0 as u8,
+        Register::Reg1 as u8,
+    ];
+    let products = [
+        (1 << 63, 1, flags::SIGN),
+        (u64::MAX, u64::MAX, flags::CARRY),
+        (1 << 62, 2, flags::OVERFLOW | flags::SIGN),
+        (1 << 63, 2, flags::ZERO | flags::CARRY | flags::OVERFLOW),
+    ];
+    for (a, b, expected) in products {
+        let mut cpu = self::cpu(&program);
+        cpu.set_reg(Register::Reg0, a);
+        cpu.set_reg(Register::Reg1, b);
+        cpu.step().unwrap();
+        assert_eq!(cpu.reg(Register::Flags), expected);
+    }
+}
+
+#[test]
+fn test_signed_arithmetic() {
+    let minus = |value: i64| imm(value as u64);
+    let program = [
+        &[Imul as u8, ImmToReg as u8][..],
+        &minus(-3),
+        &[Register::Reg0 as u8],
+        &[Idiv as u8, ImmToReg as u8],
+        &minus(-2),
+        &[Register::Accumulator as u8],
+        &[Imod as u8, ImmToReg as u8],
+        &imm(4),
+        &[Register::Accumulator as u8],
+        &[
+            Sar as u8,
+            RegToReg as u8,
+            Register::Reg1 as u8,
+            Register::Reg2 as u8,
+        ],
+        &[
+            Cmp as u8,
+            RegToReg as u8,
+            Register::Reg1 as u8,
+            Register::Reg0 as u8,
+        ],
+        &[Jslt as u8, Literal as u8],
+        &imm(0x80),
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+    cpu.set_reg(Register::Reg0, 5);
+    cpu.set_reg(Register::Reg1, -16i64 as u64);
+    cpu.set_reg(Register::Reg2, 100);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.reg(Register::Accumulator) as i64, -15);
+    cpu.step().unwrap();
+    assert_eq!(cpu.reg(Register::Accumulator) as i64, 7);
+    cpu.step().unwrap();
+    assert_eq!(cpu.reg(Register::Accumulator) as i64, 3);
+    cpu.step().unwrap();
+    assert_eq!(cpu.reg(Register::Accumulator) as i64, -1);
+
+    // -16 is less than 5 when signed, but not when unsigned
+    cpu.run_for(2).unwrap();
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0x80);
+    assert!(cpu.reg(Register::Flags) & flags::CARRY == 0);
+}
+
+#[test]
+fn test_divide_by_zero_trap() {
+    let table = 0x800;
+    let handler = 42;
+    let program = [
+        // Install the handler in the vector table
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(table),
+        &[Register::VectorTable as u8],
+        &[Mov as u8, ImmToMem as u8],
+        &imm(handler),
+        &imm(table + 8 * vectors::DIVIDE_BY_ZERO),
+        // Divide by zero
+        &[Div as u8, ImmToReg as u8],
+        &imm(0),
+        &[Register::Reg0 as u8],
+        &[Hlt as u8, Null as u8],
+        // Handler
+        &[Mov as u8, ImmToReg as u8],
+        &imm(0x99),
+        &[Register::Accumulator as u8],
+        &[Iret as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+
+    cpu.run_for(2).unwrap();
+    let outcome = cpu.step().unwrap();
+    assert_eq!(
+        outcome.flow,
+        Flow::Trap {
+            vector: vectors::DIVIDE_BY_ZERO,
+            target: handler,
+        }
+    );
+
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+    assert_eq!(cpu.reg(Register::Accumulator), 0x99);
+}
+
+#[test]
+fn test_register_based_addressing() {
+    use Register::*;
+    let program = [
+        &[
+            Mov as u8,
+            IdxToReg as u8,
+            Reg0 as u8,
+            Reg1 as u8,
+            8,
+            Reg2 as u8,
+        ][..],
+        &[Mov as u8, RegToDisp as u8, Reg2 as u8, Reg0 as u8],
+        &imm(0x20),
+        &[Lod as u8, IndToReg as u8, Reg0 as u8, Reg3 as u8],
+        &[Add as u8, DispToReg as u8, Reg0 as u8],
+        &imm(8),
+        &[Reg3 as u8],
+        &[Str as u8, ImmToDisp as u8],
+        &imm(0x55),
+        &[FramePointer as u8],
+        &imm(-16i64 as u64),
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+    let array: Vec<u8> = [10u64, 20, 30]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    cpu.write_mem(0x800, &array).unwrap();
+    cpu.set_reg(Reg0, 0x800);
+    cpu.set_reg(Reg1, 2);
+    cpu.set_reg(FramePointer, 0x910);
+    cpu.run().unwrap();
+
+    assert_eq!(cpu.reg(Reg2), 30);
+    assert_eq!(cpu.read_mem(0x820, 1).unwrap(), vec![30]);
+    assert_eq!(cpu.reg(Reg3), 10);
+    assert_eq!(cpu.reg(Accumulator), 30);
+    assert_eq!(cpu.read_mem(0x900, 1).unwrap(), vec![0x55]);
+}
+
+#[test]
+fn test_sized_loads_and_stores() {
+    use Register::*;
+    let program = [
+        &[Lodb as u8, MemToReg as u8][..],
+        &imm(0x800),
+        &[Reg0 as u8],
+        &[Lodsb as u8, MemToReg as u8],
+        &imm(0x800),
+        &[Reg1 as u8],
+        &[Lodsh as u8, MemToReg as u8],
+        &imm(0x800),
+        &[Reg2 as u8],
+        &[Strw as u8, ImmToMem as u8],
+        &imm(0x1122_3344_5566_7788),
+        &imm(0x810),
+        &[Movb as u8, MemToMem as u8],
+        &imm(0x810),
+        &imm(0x818),
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+    cpu.write_mem(0x800, &[0xF0, 0x7F]).unwrap();
+    cpu.run().unwrap();
+
+    assert_eq!(cpu.reg(Reg0), 0xF0);
+    assert_eq!(cpu.reg(Reg1), 0xFFFF_FFFF_FFFF_FFF0);
+    assert_eq!(cpu.reg(Reg2), 0x7FF0);
+    assert_eq!(
+        cpu.read_mem(0x810, 5).unwrap(),
+        vec![0x88, 0x77, 0x66, 0x55, 0x00]
+    );
+    assert_eq!(cpu.read_mem(0x818, 2).unwrap(), vec![0x88, 0x00]);
+}
+
+#[test]
+fn test_position_independent_code() {
+    use Register::*;
+    #[rustfmt::skip]
+    let program = [
+        // Load the constant relative to the next instruction
+        &[Lod as u8, RelToReg as u8][..], &imm(23), &[Reg0 as u8],
+        // Jump over the next instruction
+        &[Jmp as u8, Relative as u8], &imm(11),
+        &[Mov as u8, ImmToReg as u8], &imm(0xBAD), &[Reg1 as u8],
+        &[Hlt as u8, Null as u8],
+        &0x1234u64.to_le_bytes(),
+    ]
+    .concat();
+
+    // Run the same code at two different addresses
+    for base in [0x00, 0x40] {
+        let mut cpu = cpu(&[vec![0; base], program.clone()].concat());
+        cpu.set_reg(InstructionPointer, base as u64);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.reg(Reg0), 0x1234);
+        assert_eq!(cpu.reg(Reg1), 0);
+        assert_eq!(cpu.reg(InstructionPointer), base as u64 + 34);
+    }
+}
+
+#[test]
+fn test_device_interrupt() {
+    use Register::*;
+    let table = 0x800;
+    let handler = 0x60;
+    let program = [
+        // Install the handler for IRQ 3 and enable interrupts
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(table),
+        &[VectorTable as u8],
+        &[Mov as u8, ImmToMem as u8],
+        &imm(handler),
+        &imm(table + 8 * (vectors::IRQ_BASE + 3)),
+        &[Sti as u8, Null as u8],
+        // Spin until the handler ran
+        &[Nop as u8, Null as u8],
+        &[Jmp as u8, Literal as u8],
+        &imm(0x1F),
+    ]
+    .concat();
+    let padding = vec![0; handler as usize - program.len()];
+    let handler_code = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(0x99),
+        &[Accumulator as u8],
+        &[Iret as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&[program, padding, handler_code].concat());
+    let line = cpu.dev_mapper.irq_line(3).unwrap();
+    assert!(cpu.dev_mapper.irq_line(IRQ_LINES).is_none());
+
+    // Nothing is taken while no line is raised
+    cpu.run_for(5).unwrap();
+    assert_eq!(cpu.reg(Accumulator), 0);
+
+    line.raise();
+    let outcome = cpu.step().unwrap();
+    assert_eq!(outcome.interrupt, Some(vectors::IRQ_BASE + 3));
+    assert_eq!(outcome.addr, handler);
+    assert!(!line.is_pending());
+    assert_eq!(cpu.reg(Flags) & flags::INTERRUPT, 0);
+
+    // IRET returns to the spin loop with interrupts enabled again
+    let sp = cpu.reg(StackPointer);
+    let outcome = cpu.step().unwrap();
+    assert!(outcome.returned());
+    assert_eq!(cpu.reg(Accumulator), 0x99);
+    assert_ne!(cpu.reg(Flags) & flags::INTERRUPT, 0);
+    assert!(cpu.reg(StackPointer) > sp);
+}
+
+#[test]
+fn test_masked_and_software_interrupts() {
+    let program = [
+        &[Cli as u8, Null as u8][..],
+        &[Nop as u8, Null as u8],
+        &[Int as u8, Literal as u8],
+        &imm(0x40),
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+    let line = cpu.dev_mapper.irq_line(0).unwrap();
+
+    // A masked request stays pending
+    line.raise();
+    cpu.run_for(2).unwrap();
+    assert!(line.is_pending());
+
+    // INT without a handler stops the CPU
+    let err = cpu.run().unwrap_err();
+    assert_eq!(
+        err,
+        VmError::UnhandledInterrupt {
+            ip: 4,
+            vector: 0x40
+        }
+    );
+}
+
+#[test]
+fn test_syscalls() {
+    use Register::*;
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(0x800),
+        &[Reg0 as u8],
+        &[Mov as u8, ImmToReg as u8],
+        &imm(3),
+        &[Reg1 as u8],
+        &[Sys as u8, Literal as u8],
+        &imm(1),
+        &[Sys as u8, Literal as u8],
+        &imm(2),
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+
+    // Fill R1 bytes at the address in R0 and return how many were written
+    cpu.register_syscall(
+        1,
+        Box::new(|ctx| {
+            let addr = ctx.reg(Reg0);
+            let len = ctx.reg(Reg1);
+            ctx.write_mem(addr, &vec![0xAB; len as usize])?;
+            ctx.set_reg(Accumulator, len);
+            Ok(())
+        }),
+    );
+
+    // Syscall 2 is not registered
+    let err = cpu.run().unwrap_err();
+    assert_eq!(
+        err,
+        VmError::InvalidSyscall {
+            ip: 0x20,
+            number: 2
+        }
+    );
+    assert_eq!(cpu.reg(Accumulator), 3);
+    assert_eq!(cpu.read_mem(0x800, 4).unwrap(), [0xAB, 0xAB, 0xAB, 0]);
+}
+
+#[test]
+fn test_breakpoints() {
+    use Register::*;
+    let program = [
+        // Count R0 up to 3
+        &[Add as u8, ImmToReg as u8][..],
+        &imm(1),
+        &[Reg0 as u8],
+        &[Mov as u8, RegToReg as u8, Accumulator as u8, Reg0 as u8],
+        &[Cmp as u8, ImmToReg as u8],
+        &imm(3),
+        &[Reg0 as u8],
+        &[Jne as u8, Literal as u8],
+        &imm(0),
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+
+    // Resuming from a breakpoint executes the instruction at it
+    cpu.add_breakpoint(0);
+    assert_eq!(cpu.run().unwrap(), StopReason::Breakpoint { addr: 0 });
+    assert_eq!(cpu.reg(Reg0), 1);
+    assert_eq!(cpu.run().unwrap(), StopReason::Breakpoint { addr: 0 });
+    assert_eq!(cpu.reg(Reg0), 2);
+
+    cpu.remove_breakpoint(0);
+    cpu.add_conditional_breakpoint(0x0B, Box::new(|ctx| ctx.reg(Accumulator) == 3));
+    assert_eq!(cpu.run().unwrap(), StopReason::Breakpoint { addr: 0x0B });
+    assert_eq!(cpu.reg(Reg0), 2);
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+}
+
+#[test]
+fn test_watchpoints() {
+    let program = [
+        &[Movb as u8, ImmToMem as u8][..],
+        &imm(0x42),
+        &imm(0x803),
+        &[Mov as u8, MemToReg as u8],
+        &imm(0x800),
+        &[Register::Reg0 as u8],
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+
+    // Instruction fetches and host accesses are not watched
+    cpu.add_watchpoint(0, 0x10, WatchKind::ReadWrite);
+    cpu.add_watchpoint(0x800, 8, WatchKind::Write);
+    cpu.read_mem(0x800, 8).unwrap();
+
+    assert_eq!(
+        cpu.run().unwrap(),
+        StopReason::Watchpoint {
+            addr: 0x803,
+            access: AccessKind::Write,
+        }
+    );
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0x12);
+
+    // Reads do not trigger a write watchpoint
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+
+    cpu.remove_watchpoint(0x800);
+    cpu.add_watchpoint(0x804, 4, WatchKind::Read);
+    cpu.set_reg(Register::InstructionPointer, 0x12);
+    assert_eq!(
+        cpu.run().unwrap(),
+        StopReason::Watchpoint {
+            addr: 0x804,
+            access: AccessKind::Read,
+        }
+    );
+}
+
+#[test]
+fn test_decode_and_backtrace() {
+    let program = [
+        &[Cal as u8, Literal as u8][..],
+        &imm(0x20),
+        &[Hlt as u8, Null as u8],
+        &[0; 0x14],
+        // 0x20
+        &[Cal as u8, Literal as u8],
+        &imm(0x40),
+        &[Ret as u8, Null as u8],
+        &[0; 0x14],
+        // 0x40
+        &[Nop as u8, Null as u8],
+        &[Ret as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+
+    let (instr, len) = cpu.decode(0x20).unwrap();
+    assert_eq!(instr.opcode, Opcode::Cal);
+    assert_eq!(len, 10);
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0);
+
+    cpu.run_for(2).unwrap();
+    let frames = cpu.backtrace();
+    let addrs: Vec<u64> = frames.iter().map(|frame| frame.addr).collect();
+    assert_eq!(addrs, [0x40, 0x2A, 0x0A]);
+    assert_eq!(frames[2].frame_pointer, 0);
+
+    // Returning restores the frame pointer of the caller
+    cpu.run_for(2).unwrap();
+    assert_eq!(cpu.reg(Register::FramePointer), frames[1].frame_pointer);
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+}
+
+// A debugger connection replaying scripted input
+struct ScriptedConnection {
+    input: std::io::Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl std::io::Read for ScriptedConnection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl std::io::Write for ScriptedConnection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for ScriptedConnection {}
+
+// Serves the packets to a stub and returns the replies
+fn gdb_session(cpu: &mut Cpu, packets: &[&str]) -> Vec<String> {
+    let mut input = Vec::new();
+    for data in packets {
+        input.extend(packet::encode(data.as_bytes()));
+        input.push(b'+');
+    }
+    let conn = ScriptedConnection {
+        input: std::io::Cursor::new(input),
+        output: Vec::new(),
+    };
+
+    let mut stub = GdbStub::new(conn);
+    stub.serve(cpu).unwrap();
+    let output = String::from_utf8(stub.into_inner().output).unwrap();
+
+    // Strip the acks and the framing of every reply
+    output
+        .split('$')
+        .skip(1)
+        .map(|reply| reply.rsplit_once('#').unwrap().0.to_string())
+        .collect()
+}
+
+#[test]
+fn test_gdb_packets() {
+    assert_eq!(packet::encode(b"OK"), b"$OK#9a");
+    assert_eq!(packet::encode(b"a#"), b"$a}\x03#e1");
+}
+
+#[test]
+fn test_gdb_session() {
+    let program = [
+        &[Cal as u8, Literal as u8][..],
+        &imm(0x20),
+        &[Hlt as u8, Null as u8],
+        &[0; 0x14],
+        // 0x20
+        &[Ret as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+
+    let replies = gdb_session(
+        &mut cpu,
+        &[
+            "qSupported:multiprocess+",
+            "qXfer:features:read:target.xml:0,1000",
+            "?",
+            "Z0,20,1",
+            "c",
+            "p1",
+            "m0,2",
+            "P0=2a00000000000000",
+            "s",
+            "z0,20,1",
+            "c",
+            "D",
+        ],
+    );
+
+    assert!(replies[0].contains("qXfer:features:read+"));
+    assert!(replies[1].starts_with('l'));
+    assert!(replies[1].contains("<reg name=\"ip\" bitsize=\"64\" type=\"code_ptr\" regnum=\"1\"/>"));
+    assert_eq!(
+        &replies[2..],
+        [
+            "S05",
+            "OK",
+            "T05swbreak:;",
+            "2000000000000000",
+            "4770",
+            "OK",
+            "S05",
+            "OK",
+            "W00",
+            "OK",
+        ]
+    );
+    assert_eq!(cpu.reg(Register::Accumulator), 0x2A);
+
+    // G writes every register and rejects a payload of any other length
+    let registers = Register::all().len();
+    let values: Vec<u8> = (1..=registers as u64).flat_map(u64::to_le_bytes).collect();
+    let all = format!("G{}", packet::to_hex(&values));
+    let short = format!("G{}", packet::to_hex(&values[8..]));
+    let long = format!("{}00", all);
+    let replies = gdb_session(&mut cpu, &[&short, &long, &all, "D"]);
+    assert_eq!(replies, ["E01", "E01", "OK", "OK"]);
+    for (value, reg) in Register::all().iter().enumerate() {
+        assert_eq!(cpu.reg(*reg), value as u64 + 1);
+    }
+}
+
+// A trace output the test can read back after the tracer is finished
+#[derive(Clone, Default)]
+struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Runs a program that loads a register, pushes it and halts with a tracer, returns the trace
+fn trace(tracer: impl FnOnce(Box<SharedBuffer>) -> Tracer) -> (Vec<u8>, Tracer) {
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(0x2A),
+        &[Register::Reg0 as u8],
+        &[Psh as u8, Register as u8, Register::Reg0 as u8],
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let buffer = SharedBuffer::default();
+    let mut cpu = cpu(&program);
+    cpu.set_tracer(tracer(Box::new(buffer.clone())));
+    cpu.run().unwrap();
+
+    let tracer = cpu.take_tracer().unwrap();
+    let output = buffer.0.borrow().clone();
+    (output, tracer)
+}
+
+#[test]
+fn test_trace_formats() {
+    let sp = (RAM_SIZE - 8) as u64;
+
+    let (text, _) = trace(|out| Tracer::new(out, TraceFormat::Text));
+    let text = String::from_utf8(text).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("0x0000000000000000  Mov ImmToReg"));
+    assert!(lines[0].ends_with("r0=0x2a"));
+    assert!(lines[1].contains(&format!("sp={:#x}", sp - 8)));
+    assert!(lines[1].contains("=0x2a/8"));
+
+    let (json, _) = trace(|out| Tracer::new(out, TraceFormat::JsonLines));
+    let records: Vec<serde_json::Value> = String::from_utf8(json)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0]["regs"]["r0"], 0x2A);
+    assert_eq!(records[1]["writes"][0]["value"], 0x2A);
+    assert_eq!(records[1]["writes"][0]["len"], 8);
+    assert_eq!(records[2]["addr"], 0x0E);
+
+    let (binary, tracer) = trace(|out| Tracer::new(out, TraceFormat::Binary));
+    assert_eq!(&binary[..4], BINARY_MAGIC);
+    assert_eq!(binary[5..13], 0u64.to_le_bytes());
+    assert_eq!(binary[13], Mov as u8);
+    assert_eq!(tracer.written(), binary.len() as u64);
+    tracer.finish().unwrap();
+}
+
+#[test]
+fn test_trace_filters_and_limit() {
+    let (json, _) =
+        trace(|out| Tracer::new(out, TraceFormat::JsonLines).with_classes(&[OpcodeClass::Stack]));
+    let text = String::from_utf8(json).unwrap();
+    assert_eq!(text.lines().count(), 1);
+    assert!(text.contains("\"instr\":\"Psh"));
+
+    let (text, _) = trace(|out| Tracer::new(out, TraceFormat::Text).with_range(0x0B..0x0F));
+    let text = String::from_utf8(text).unwrap();
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.starts_with("0x000000000000000b"));
+
+    // The record that does not fit and every later one are dropped
+    let (text, tracer) = trace(|out| Tracer::new(out, TraceFormat::Text).with_limit(60));
+    assert_eq!(String::from_utf8(text).unwrap().lines().count(), 1);
+    assert!(tracer.truncated());
+}
+
+const SENSOR_ADDR: u64 = 0x2000;
+
+// A device that is not memory, reading it returns a value the test picks
+struct Sensor(u64);
+
+impl Device for Sensor {
+    fn read(&self, addr: u64) -> Result<u8, BusFault> {
+        Ok((self.0 >> (addr * 8)) as u8)
+    }
+
+    fn write(&mut self, addr: u64, _value: u8) -> Result<(), BusFault> {
+        Err(BusFault::write(addr))
+    }
+
+    fn size(&self) -> usize {
+        8
+    }
+}
+
+impl Snapshot for Sensor {}
+
+// Creates a CPU running a program that takes IRQ 0, reads the sensor and makes syscall 1
+fn sensor_cpu(sensor: u64) -> Cpu {
+    use Register::*;
+    let table = 0x800;
+    let handler = 0x60;
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(table),
+        &[VectorTable as u8],
+        &[Mov as u8, ImmToMem as u8],
+        &imm(handler),
+        &imm(table + 8 * vectors::IRQ_BASE),
+        &[Sti as u8, Null as u8],
+        &[Mov as u8, MemToReg as u8],
+        &imm(SENSOR_ADDR),
+        &[Reg0 as u8],
+        &[Sys as u8, Literal as u8],
+        &imm(1),
+        &[Mov as u8, RegToMem as u8, Reg0 as u8],
+        &imm(0xA00),
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let padding = vec![0; handler as usize - program.len()];
+    let handler_code = [
+        &[Mov as u8, ImmToMem as u8][..],
+        &imm(0x77),
+        &imm(0xA10),
+        &[Iret as u8, Null as u8],
+    ]
+    .concat();
+
+    let mut cpu = cpu(&[program, padding, handler_code].concat());
+    cpu.attach(
+        Box::new(Sensor(sensor)),
+        String::from("sensor"),
+        SENSOR_ADDR,
+    );
+    cpu
+}
+
+// Records a run of the sensor program with an IRQ raised and a syscall answering 0x55
+fn recorded_sensor_cpu(interval: u64) -> Cpu {
+    let mut cpu = sensor_cpu(0x1234);
+    cpu.register_syscall(
+        1,
+        Box::new(|ctx| {
+            ctx.write_mem(0xA20, &[0x42])?;
+            ctx.set_reg(Register::Accumulator, 0x55);
+            Ok(())
+        }),
+    );
+    cpu.dev_mapper.irq_line(0).unwrap().raise();
+
+    cpu.start_recording(interval);
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+    cpu
+}
+
+#[test]
+fn test_record_and_replay() {
+    let mut recorded = recorded_sensor_cpu(4);
+    let log = recorded.stop_recording().unwrap();
+    assert_eq!(log.steps, 9);
+    assert_eq!(log.interrupts, vec![(3, 0)]);
+    assert_eq!(log.reads, vec![Ok(0x1234)]);
+    assert_eq!(log.syscalls[0].step, 6);
+
+    // Another sensor value, no pending IRQ and no syscall handler, the log provides all of them
+    let mut replayed = sensor_cpu(0x9999);
+    replayed.replay(log.clone(), 4);
+    assert_eq!(replayed.run().unwrap(), StopReason::Halted);
+    assert_eq!(replayed.registers(), recorded.registers());
+    assert_eq!(
+        replayed.read_mem(0xA00, 0x30).unwrap(),
+        recorded.read_mem(0xA00, 0x30).unwrap()
+    );
+    assert_eq!(replayed.read_mem(0xA00, 2).unwrap(), [0x34, 0x12]);
+
+    // Nothing was left to record
+    assert_eq!(replayed.stop_recording().unwrap(), log);
+    assert_eq!(sensor_cpu(0).stop_recording(), None::<ReplayLog>);
+}
+
+#[test]
+fn test_reverse_execution() {
+    use Register::*;
+    let mut cpu = recorded_sensor_cpu(2);
+    let end = cpu.registers();
+    assert_eq!(cpu.recording_position(), Some((9, 9)));
+
+    // Back to before HLT, then before the store
+    cpu.reverse_step().unwrap();
+    assert_eq!(cpu.reg(InstructionPointer), 0x3F);
+    cpu.reverse_step().unwrap();
+    assert_eq!(cpu.reg(InstructionPointer), 0x34);
+    assert_eq!(cpu.read_mem(0xA00, 1).unwrap(), [0]);
+    assert_eq!(cpu.recording_position(), Some((7, 9)));
+
+    // Back to the syscall, which is undone
+    cpu.add_breakpoint(0x2A);
+    assert_eq!(
+        cpu.reverse_continue().unwrap(),
+        StopReason::Breakpoint { addr: 0x2A }
+    );
+    assert_eq!(cpu.read_mem(0xA20, 1).unwrap(), [0]);
+    assert_eq!(cpu.read_mem(0xA10, 1).unwrap(), [0x77]);
+    assert_eq!(cpu.reg(Reg0), 0x1234);
+
+    // Back to the start, where the handler did not run yet
+    assert_eq!(cpu.reverse_continue().unwrap(), StopReason::RecordingStart);
+    assert_eq!(cpu.reg(InstructionPointer), 0);
+    assert_eq!(cpu.reg(VectorTable), 0);
+    assert_eq!(cpu.read_mem(0xA10, 1).unwrap(), [0]);
+    assert_eq!(cpu.reverse_step().unwrap(), StopReason::RecordingStart);
+
+    // Running again replays the same run
+    assert_eq!(cpu.run().unwrap(), StopReason::Breakpoint { addr: 0x2A });
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+    assert_eq!(cpu.registers(), end);
+    assert_eq!(cpu.read_mem(0xA20, 1).unwrap(), [0x42]);
+
+    // A debugger executes backwards with bs and bc
+    let replies = gdb_session(&mut cpu, &["bs", "bc", "D"]);
+    assert_eq!(replies, ["S05", "T05swbreak:;", "OK"]);
+    assert_eq!(cpu.reg(InstructionPointer), 0x2A);
+}
+
+#[test]
+fn test_snapshot_and_restore() {
+    use Register::*;
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(0x2A),
+        &[Reg0 as u8],
+        &[Mov as u8, RegToMem as u8, Reg0 as u8],
+        &imm(0x800),
+        &[Inc as u8, Register as u8, Reg0 as u8],
+        &[Mov as u8, RegToMem as u8, Reg0 as u8],
+        &imm(0x808),
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let mut original = cpu(&program);
+    original.dev_mapper.irq_line(5).unwrap().raise();
+    original.run_for(2).unwrap();
+
+    // Only the RAM page with the stack and the data and the ROM are stored
+    let bytes = original.snapshot().to_bytes();
+    assert!(bytes.len() < 3 * SNAPSHOT_PAGE_SIZE);
+    let state = SaveState::from_bytes(&bytes).unwrap();
+    assert_eq!(state, original.snapshot());
+
+    let mut restored = cpu(&[]);
+    restored.restore(&state).unwrap();
+    assert_eq!(restored.registers(), original.registers());
+    assert_eq!(restored.read_mem(0, 0x20).unwrap(), program[..0x20]);
+    assert_eq!(restored.read_mem(0x800, 1).unwrap(), [0x2A]);
+    assert_eq!(restored.dev_mapper.interrupts().pending(), Some(5));
+
+    // Both go on the same way
+    original.run().unwrap();
+    restored.run().unwrap();
+    assert_eq!(restored.registers(), original.registers());
+    assert_eq!(
+        restored.read_mem(0x800, 16).unwrap(),
+        original.read_mem(0x800, 16).unwrap()
+    );
+}
+
+#[test]
+fn test_snapshot_errors() {
+    // A large RAM with a single byte written saves a single page
+    let mut ram = Ram::new(1 << 24);
+    ram.write(0x123456, 0xAA).unwrap();
+    let state = ram.save();
+    assert_eq!(state.len(), 8 + SNAPSHOT_PAGE_SIZE);
+    let mut loaded = Ram::new(1 << 24);
+    loaded.load(&state).unwrap();
+    assert_eq!(loaded.read(0x123456).unwrap(), 0xAA);
+    assert!(Ram::new(16).load(&state).is_err());
+
+    let bytes = cpu(&[]).snapshot().to_bytes();
+    assert!(matches!(
+        SaveState::from_bytes(b"nope"),
+        Err(SnapshotError::InvalidMagic)
+    ));
+    let mut newer = bytes.clone();
+    newer[4] = 9;
+    assert!(matches!(
+        SaveState::from_bytes(&newer),
+        Err(SnapshotError::UnsupportedVersion(9))
+    ));
+    assert!(matches!(
+        SaveState::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(SnapshotError::Truncated)
+    ));
+
+    // A machine without the ROM is left untouched
+    let mut other = MachineConfig::new(RAM_SIZE).build().unwrap();
+    other.set_reg(Register::Reg0, 7);
+    let state = SaveState::from_bytes(&bytes).unwrap();
+    assert!(matches!(
+        other.restore(&state),
+        Err(SnapshotError::MissingDevice { start: 0, .. })
+    ));
+    assert_eq!(other.reg(Register::Reg0), 7);
+}
+
+#[test]
+fn test_sparse_ram() {
+    // Pages are allocated on the first non-zero write
+    let mut ram = SparseRam::new(1 << 40);
+    assert_eq!(ram.resident_size(), 0);
+    assert_eq!(ram.read(0x12_3456_789A).unwrap(), 0);
+    ram.write(0x12_3456_789A, 0).unwrap();
+    assert_eq!(ram.resident_size(), 0);
+    ram.write(0x12_3456_789A, 0xAA).unwrap();
+    ram.write(0x12_3456_789B, 0xBB).unwrap();
+    assert_eq!(ram.resident_size(), SNAPSHOT_PAGE_SIZE);
+    assert_eq!(ram.read(0x12_3456_789B).unwrap(), 0xBB);
+
+    // Out of range accesses fault
+    assert_eq!(ram.read(1 << 40), Err(BusFault::read(1 << 40)));
+    assert_eq!(ram.write(1 << 40, 1), Err(BusFault::write(1 << 40)));
+
+    // Snapshots hold only the allocated pages and match the dense RAM encoding
+    let state = ram.save();
+    assert_eq!(state.len(), 8 + SNAPSHOT_PAGE_SIZE);
+    let mut loaded = SparseRam::new(1 << 40);
+    loaded.write(0, 1).unwrap();
+    loaded.load(&state).unwrap();
+    assert_eq!(loaded.read(0).unwrap(), 0);
+    assert_eq!(loaded.read(0x12_3456_789A).unwrap(), 0xAA);
+    assert_eq!(loaded.resident_size(), SNAPSHOT_PAGE_SIZE);
+
+    let mut dense = SparseRam::new(0x1800);
+    dense.write(0x17FF, 0xCC).unwrap();
+    let mut ram = Ram::new(0x1800);
+    ram.load(&dense.save()).unwrap();
+    assert_eq!(ram.read(0x17FF).unwrap(), 0xCC);
+    assert_eq!(ram.save(), dense.save());
+}
+
+#[test]
+fn test_cpu_with_sparse_ram() {
+    let mut cpu = MachineConfig::new(RAM_SIZE)
+        .ram_kind(RamKind::Sparse)
+        .rom("rom", 0, ROM_SIZE, &[Opcode::Hlt as u8, Null as u8])
+        .build()
+        .unwrap();
+
+    cpu.write_mem(0x800, &[1, 2, 3]).unwrap();
+    assert_eq!(cpu.read_mem(0x800, 3).unwrap(), [1, 2, 3]);
+    cpu.run().unwrap();
+}
+
+#[test]
+fn test_machine_config() {
+    // The stack starts at the last slot of the RAM and the entry is set
+    let cpu = MachineConfig::new(RAM_SIZE)
+        .rom("rom", 0, ROM_SIZE, &[Opcode::Hlt as u8, Null as u8])
+        .rom("boot", 0x2000, ROM_SIZE, &[Opcode::Hlt as u8, Null as u8])
+        .entry(0x2000)
+        .build()
+        .unwrap();
+    assert_eq!(cpu.reg(Register::StackPointer), (RAM_SIZE - 8) as u64);
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0x2000);
+    assert_eq!(
+        MachineConfig::new(RAM_SIZE)
+            .build()
+            .unwrap()
+            .reg(Register::StackPointer),
+        (RAM_SIZE - 8) as u64
+    );
+
+    let rom = |base| MachineConfig::new(RAM_SIZE).rom("rom", base, ROM_SIZE, &[]);
+    assert_eq!(
+        rom(0).rom("other", 0xFF, 1, &[]).build().err(),
+        Some(ConfigError::Overlap {
+            first: String::from("rom"),
+            second: String::from("other")
+        })
+    );
+    assert!(rom(0).rom("other", 0x100, 1, &[]).build().is_ok());
+    assert_eq!(
+        rom(0).rom("rom", 0x100, 1, &[]).build().err(),
+        Some(ConfigError::DuplicateName {
+            name: String::from("rom")
+        })
+    );
+    assert_eq!(
+        rom(0).rom("RAM", 0x100, 1, &[]).build().err(),
+        Some(ConfigError::DuplicateName {
+            name: String::from("RAM")
+        })
+    );
+    assert_eq!(
+        rom(0).stack_pointer(RAM_SIZE as u64 - 4).build().err(),
+        Some(ConfigError::StackOutsideRam {
+            sp: RAM_SIZE as u64 - 4
+        })
+    );
+    assert_eq!(
+        rom(0).stack_pointer(0xF8).build().err(),
+        Some(ConfigError::StackShadowed {
+            sp: 0xF8,
+            name: String::from("rom")
+        })
+    );
+    assert_eq!(
+        MachineConfig::new(4).build().err(),
+        Some(ConfigError::StackOutsideRam { sp: 0 })
+    );
+    assert_eq!(
+        rom(0).rom("big", 0x2000, 1, &[1, 2]).build().err(),
+        Some(ConfigError::ImageTooLarge {
+            name: String::from("big"),
+            size: 1,
+            len: 2
+        })
+    );
+    assert_eq!(
+        rom(u64::MAX).build().err(),
+        Some(ConfigError::OutOfAddressSpace {
+            name: String::from("rom")
+        })
+    );
+    assert_eq!(
+        rom(0).entry(0x2000).build().err(),
+        Some(ConfigError::EntryUnmapped { ip: 0x2000 })
+    );
+}
+
+#[test]
+fn test_machine_file() {
+    let dir = std::env::temp_dir().join(format!("slang-machine-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let program = [
+        &[Mov as u8, MemToReg as u8][..],
+        &imm(SENSOR_ADDR),
+        &[Register::Reg0 as u8],
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    std::fs::write(dir.join("sensor.bin"), &program).unwrap();
+    let text = r#"
+        memory = 0x1000
+        ram = "sparse"
+
+        [[device]]
+        type = "rom"
+        size = 0x100
+        base = 0
+        image = "sensor.bin"
+
+        [[device]]
+        type = "sensor"
+        name = "thermometer"
+        base = 0x2000
+        value = 0x1234
+        irq = 3
+    "#;
+    std::fs::write(dir.join("machine.toml"), text).unwrap();
+
+    // Device types are added to the registry by the embedder
+    let file = MachineFile::load(dir.join("machine.toml")).unwrap();
+    assert!(matches!(
+        file.build(&DeviceRegistry::default()),
+        Err(MachineFileError::UnknownDeviceType(kind)) if kind == "sensor"
+    ));
+    let mut registry = DeviceRegistry::default();
+    let sensor_line = Rc::new(RefCell::new(None));
+    let line = sensor_line.clone();
+    registry.register(
+        "sensor",
+        Box::new(move |spec, irq_line| {
+            *line.borrow_mut() = irq_line;
+            Ok(Box::new(Sensor(spec.integer("value")?.unwrap_or(0))))
+        }),
+    );
+    let mut cpu = file.build(&registry).unwrap();
+    assert_eq!(cpu.reg(Register::StackPointer), (RAM_SIZE - 8) as u64);
+
+    // The line handed to the device raises interrupts on the CPU
+    sensor_line.borrow().as_ref().unwrap().raise();
+    assert_eq!(cpu.dev_mapper.interrupts().requests(), 1 << 3);
+    cpu.dev_mapper.interrupts().set_requests(0);
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+    assert_eq!(cpu.reg(Register::Reg0), 0x1234);
+
+    // ROMs are mapped read and execute only like MachineConfig::rom unless the file sets permissions
+    let store = [&[Mov as u8, ImmToMem as u8][..], &imm(5), &imm(0x10)].concat();
+    std::fs::write(dir.join("store.bin"), &store).unwrap();
+    let store = |permissions: &str| {
+        let text = format!(
+            "memory = 0x1000\n[[device]]\ntype = \"rom\"\nbase = 0\nsize = 0x100\n\
+             image = \"store.bin\"\n{}",
+            permissions
+        );
+        MachineFile::parse(&text, &dir)
+            .unwrap()
+            .build(&registry)
+            .unwrap()
+    };
+    let mut rom = store("");
+    assert_eq!(
+        rom.dev_mapper.find_by_name("rom").unwrap().permissions,
+        Permissions::READ | Permissions::EXECUTE
+    );
+    assert_eq!(
+        rom.run(),
+        Err(VmError::BusFault {
+            ip: 0,
+            addr: 0x10,
+            kind: AccessKind::Write
+        })
+    );
+    let rom = store("permissions = \"rwx\"");
+    assert_eq!(
+        rom.dev_mapper.find_by_name("rom").unwrap().permissions,
+        Permissions::ALL
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Fields are checked while parsing and the layout while building
+    let parse = |text: &str| MachineFile::parse(text, &dir);
+    assert!(matches!(
+        parse("memory = "),
+        Err(MachineFileError::Parse(_))
+    ));
+    assert!(matches!(
+        parse("memory = -1"),
+        Err(MachineFileError::InvalidField(field)) if field == "memory"
+    ));
+    assert!(matches!(
+        parse("memory = 0x1000\n[[device]]\ntype = \"ram\""),
+        Err(MachineFileError::InvalidField(field)) if field == "device.ram.base"
+    ));
+    assert!(matches!(
+        parse("memory = 0x1000\n[[device]]\ntype = \"stdout\"\nbase = 0x2000\nirq = 64"),
+        Err(MachineFileError::InvalidField(field)) if field == "device.stdout.irq"
+    ));
+    assert!(matches!(
+        parse("memory = 0x1000\nmemroy = 0x2000"),
+        Err(MachineFileError::InvalidField(field)) if field == "memroy"
+    ));
+    assert!(matches!(
+        parse("memory = 0x1000\nentyr = 0x10"),
+        Err(MachineFileError::InvalidField(field)) if field == "entyr"
+    ));
+
+    // Options the device type does not read are rejected while building
+    let typo = parse(
+        "memory = 0x1000\n[[device]]\ntype = \"rom\"\nbase = 0x2000\nsize = 0x10\n\
+         imgae = \"store.bin\"",
+    )
+    .unwrap();
+    assert!(matches!(
+        typo.build(&registry),
+        Err(MachineFileError::InvalidField(field)) if field == "device.rom.imgae"
+    ));
+    let overlap = parse(
+        "memory = 0x1000\n[[device]]\ntype = \"ram\"\nbase = 0x2000\nsize = 0x10\n\
+         [[device]]\ntype = \"stdout\"\nbase = 0x2008",
+    )
+    .unwrap();
+    assert!(matches!(
+        overlap.build(&registry),
+        Err(MachineFileError::Config(ConfigError::Overlap { .. }))
+    ));
+}
+
+// Creates a ROM filled with a byte
+fn filled_rom(size: usize, byte: u8) -> Box<Rom> {
+    let mut rom = Rom::new(size);
+    rom.flash(&vec![byte; size]);
+    Box::new(rom)
+}
+
+#[test]
+fn test_overlapping_regions() {
+    // The device mapped last wins where devices overlap
+    let mut mapper = DeviceMapper::new();
+    mapper.map(
+        filled_rom(0x100, 1),
+        String::from("low"),
+        0,
+        Permissions::ALL,
+    );
+    mapper.map(
+        filled_rom(0x20, 2),
+        String::from("middle"),
+        0x40,
+        Permissions::ALL,
+    );
+    mapper.map(
+        filled_rom(0x100, 3),
+        String::from("high"),
+        0x50,
+        Permissions::ALL,
+    );
+    mapper.map(
+        filled_rom(0x10, 4),
+        String::from("inner"),
+        0x80,
+        Permissions::ALL,
+    );
+
+    let read = |mapper: &DeviceMapper, addr| mapper.peek(addr).unwrap();
+    assert_eq!(read(&mapper, 0x3F), 1);
+    assert_eq!(read(&mapper, 0x40), 2);
+    assert_eq!(read(&mapper, 0x4F), 2);
+    assert_eq!(read(&mapper, 0x50), 3);
+    assert_eq!(read(&mapper, 0x80), 4);
+    assert_eq!(read(&mapper, 0x8F), 4);
+    assert_eq!(read(&mapper, 0x91), 3);
+    assert_eq!(read(&mapper, 0x3F), 1);
+
+    // Unmapping uncovers the devices below, also after a cached lookup
+    mapper.unmap(0x50);
+    assert_eq!(read(&mapper, 0x51), 2);
+    assert_eq!(read(&mapper, 0x70), 1);
+    assert_eq!(read(&mapper, 0x80), 4);
+    mapper.unmap(0);
+    assert_eq!(mapper.peek(0x70), Err(BusFault::read(0x70)));
+    assert_eq!(mapper.write(0x70, 1), Err(BusFault::write(0x70)));
+
+    // Devices can reach the end of the address space
+    mapper.map(
+        filled_rom(0x10, 5),
+        String::from("top"),
+        u64::MAX - 0x10,
+        Permissions::ALL,
+    );
+    assert_eq!(read(&mapper, u64::MAX - 1), 5);
+}
+
+#[test]
+fn test_region_bounds() {
+    // Regions are half-open, the byte past a device belongs to what is below it
+    let mut mapper = DeviceMapper::new();
+    mapper.map(
+        Box::new(Ram::new(0x10)),
+        String::from("ram"),
+        0x100,
+        Permissions::ALL,
+    );
+    mapper.map(
+        filled_rom(0x10, 1),
+        String::from("rom"),
+        0x110,
+        Permissions::ALL,
+    );
+    mapper.map(
+        filled_rom(0, 9),
+        String::from("empty"),
+        0x108,
+        Permissions::ALL,
+    );
+    mapper.write(0x10F, 0xAA).unwrap();
+    assert_eq!(mapper.peek(0x10F).unwrap(), 0xAA);
+    assert_eq!(mapper.peek(0x110).unwrap(), 1);
+    assert_eq!(mapper.peek(0x108).unwrap(), 0);
+    assert_eq!(mapper.peek(0x120), Err(BusFault::read(0x120)));
+    assert_eq!(mapper.write(0x120, 1), Err(BusFault::write(0x120)));
+    assert_eq!(mapper.write(0x110, 1), Err(BusFault::write(0x110)));
+
+    // 64-bit accesses straddling two devices take each byte from its own device
+    mapper.write64(0x108, 0x1122_3344_5566_7788).unwrap();
+    assert_eq!(mapper.read64(0x108).unwrap(), 0x1122_3344_5566_7788);
+    assert_eq!(mapper.read64(0x10C).unwrap(), 0x0101_0101_1122_3344);
+    assert_eq!(mapper.read64(0x11C), Err(BusFault::read(0x120)));
+    assert_eq!(mapper.write64(0x10C, u64::MAX), Err(BusFault::write(0x110)));
+    assert_eq!(mapper.peek(0x10F).unwrap(), 0xFF);
+
+    // and respect the priority of overlapping devices byte by byte
+    mapper.map(
+        filled_rom(2, 7),
+        String::from("window"),
+        0x103,
+        Permissions::ALL,
+    );
+    assert_eq!(mapper.read64(0x100).unwrap(), 0x0000_0007_0700_0000);
+    assert_eq!(mapper.write64(0x100, u64::MAX), Err(BusFault::write(0x103)));
+    assert_eq!(mapper.peek(0x102).unwrap(), 0xFF);
+    assert_eq!(mapper.peek(0x105).unwrap(), 0);
+
+    // Accesses at the end of the address space wrap around
+    let mut mapper = DeviceMapper::new();
+    mapper.map(
+        filled_rom(4, 5),
+        String::from("top"),
+        u64::MAX - 3,
+        Permissions::ALL,
+    );
+    mapper.map(
+        filled_rom(4, 6),
+        String::from("bottom"),
+        0,
+        Permissions::ALL,
+    );
+    assert_eq!(mapper.peek(u64::MAX).unwrap(), 5);
+    assert_eq!(mapper.read64(u64::MAX - 3).unwrap(), 0x0606_0606_0505_0505);
+}
+
+// Creates a machine with a data RAM that can not be executed, running the program with the
+// handler of bus faults storing R0 and R1 at 0xA00 and 0xA08
+fn bus_fault_cpu(program: &[&[u8]]) -> Cpu {
+    use Register::*;
+    let table = 0x800;
+    let handler = 0x60;
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(table),
+        &[VectorTable as u8],
+        &[Mov as u8, ImmToMem as u8],
+        &imm(handler),
+        &imm(table + 8 * vectors::BUS_FAULT),
+        &program.concat(),
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let padding = vec![0; handler as usize - program.len()];
+    let handler_code = [
+        &[Mov as u8, RegToMem as u8, Reg0 as u8][..],
+        &imm(0xA00),
+        &[Mov as u8, RegToMem as u8, Reg1 as u8],
+        &imm(0xA08),
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+
+    MachineConfig::new(RAM_SIZE)
+        .rom(
+            "rom",
+            0,
+            ROM_SIZE,
+            &[program, padding, handler_code].concat(),
+        )
+        .device_with(
+            "data",
+            0x3000,
+            Box::new(Ram::new(0x100)),
+            Permissions::READ | Permissions::WRITE,
+        )
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_bus_fault_permissions() {
+    let mut mapper = DeviceMapper::new();
+    mapper.map(
+        Box::new(Ram::new(0x10)),
+        String::from("ram"),
+        0,
+        Permissions::parse("r-x").unwrap(),
+    );
+    assert_eq!(mapper.peek(0).unwrap(), 0);
+    assert_eq!(mapper.fetch64(0).unwrap(), 0);
+    assert_eq!(mapper.write(4, 1), Err(BusFault::write(4)));
+    assert_eq!(mapper.write64(0, 1), Err(BusFault::write(0)));
+    mapper.map(
+        Box::new(Ram::new(0x10)),
+        String::from("data"),
+        0x10,
+        Permissions::READ | Permissions::WRITE,
+    );
+    mapper.write(0x10, 1).unwrap();
+    assert_eq!(mapper.fetch(0x10), Err(BusFault::execute(0x10)));
+    assert_eq!(mapper.fetch64(0x0C), Err(BusFault::execute(0x10)));
+    assert_eq!(mapper.peek(0x20), Err(BusFault::read(0x20)));
+    assert_eq!(Permissions::parse("rwy"), None);
+
+    // A store that straddles a read-only region writes none of its bytes
+    mapper.map(
+        Box::new(Ram::new(0x10)),
+        String::from("read-only"),
+        0x20,
+        Permissions::READ,
+    );
+    assert_eq!(mapper.write64(0x1C, u64::MAX), Err(BusFault::write(0x20)));
+    assert_eq!(mapper.peek64(0x18).unwrap(), 0);
+    assert_eq!(
+        mapper.write_sized(0x1E, u64::MAX, 4),
+        Err(BusFault::write(0x20))
+    );
+    assert_eq!(mapper.peek64(0x18).unwrap(), 0);
+
+    // The handler finds the address and the kind of a faulting write
+    let mut cpu = bus_fault_cpu(&[&[Mov as u8, ImmToMem as u8], &imm(5), &imm(0x10)]);
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+    let fault = |cpu: &mut Cpu| {
+        let data = cpu.read_mem(0xA00, 16).unwrap();
+        let word = |i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+        (word(0), word(8))
+    };
+    assert_eq!(fault(&mut cpu), (0x10, AccessKind::Write.code()));
+
+    // Fetching from memory that can not be executed enters the handler with the fetch address saved
+    let mut cpu = bus_fault_cpu(&[&[Jmp as u8, Literal as u8], &imm(0x3004)]);
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+    assert_eq!(fault(&mut cpu), (0x3004, AccessKind::Execute.code()));
+    let saved_ip = cpu
+        .read_mem(cpu.reg(Register::FramePointer) + 8 * 11, 8)
+        .unwrap();
+    assert_eq!(u64::from_le_bytes(saved_ip.try_into().unwrap()), 0x3004);
+
+    // An interrupt whose handler can not be fetched enters the bus fault handler and reports both
+    let mut cpu = bus_fault_cpu(&[
+        &[Mov as u8, ImmToMem as u8],
+        &imm(0x3000),
+        &imm(0x800 + 8 * vectors::IRQ_BASE),
+        &[Sti as u8, Null as u8],
+    ]);
+    cpu.run_for(4).unwrap();
+    cpu.dev_mapper.irq_line(0).unwrap().raise();
+    let outcome = cpu.step().unwrap();
+    assert_eq!(outcome.interrupt, Some(vectors::IRQ_BASE));
+    assert_eq!(outcome.fetch_fault, Some(0x3000));
+    assert_eq!(outcome.addr, 0x60);
+    assert_eq!(cpu.run().unwrap(), StopReason::Halted);
+    assert_eq!(fault(&mut cpu), (0x3000, AccessKind::Execute.code()));
+
+    // Without a handler the fault stops the CPU
+    let mut unhandled = self::cpu(&[&[Jmp as u8, Literal as u8][..], &imm(0x3004)].concat());
+    assert_eq!(
+        unhandled.run(),
+        Err(VmError::BusFault {
+            ip: 0x3004,
+            addr: 0x3004,
+            kind: AccessKind::Execute
+        })
+    );
+}
+
+#[test]
+fn test_device_introspection() {
+    let mut cpu = cpu(&[Hlt as u8, Null as u8]);
+    cpu.attach(Box::new(Sensor(7)), String::from("sensor"), SENSOR_ADDR);
+    let regions: Vec<RegionInfo> = cpu.dev_mapper.regions().collect();
+    assert_eq!(
+        regions,
+        [
+            RegionInfo {
+                name: "sensor",
+                start: SENSOR_ADDR,
+                end: SENSOR_ADDR + 8,
+                permissions: Permissions::ALL
+            },
+            RegionInfo {
+                name: "rom",
+                start: 0,
+                end: ROM_SIZE as u64,
+                permissions: Permissions::READ | Permissions::EXECUTE
+            },
+            RegionInfo {
+                name: "RAM",
+                start: 0,
+                end: RAM_SIZE as u64,
+                permissions: Permissions::ALL
+            },
+        ]
+    );
+    assert_eq!(cpu.dev_mapper.find_by_name("rom"), Some(regions[1]));
+    assert_eq!(cpu.dev_mapper.find_by_name("missing"), None);
+
+    // The host reaches the concrete devices to change them at runtime
+    assert!(cpu.dev_mapper.get::<Ram>("rom").is_none());
+    assert_eq!(cpu.dev_mapper.get::<Sensor>("sensor").unwrap().0, 7);
+    cpu.dev_mapper
+        .with_device_mut("rom", |rom: &mut Rom| {
+            rom.flash(&[Nop as u8, Null as u8, Hlt as u8, Null as u8])
+        })
+        .unwrap();
+    assert_eq!(
+        cpu.read_mem(0, 3).unwrap(),
+        [Nop as u8, Null as u8, Hlt as u8]
+    );
+
+    // Remapping keeps the priority of the device
+    assert_eq!(cpu.dev_mapper.remap("rom", 0x200), Some(0));
+    assert_eq!(cpu.read_mem(0x200, 1).unwrap(), [Nop as u8]);
+    assert_eq!(cpu.read_mem(0, 1).unwrap(), [0]);
+    assert_eq!(cpu.dev_mapper.remap("missing", 0), None);
+
+    let sensor = cpu.dev_mapper.unmap_by_name("sensor").unwrap();
+    assert_eq!(sensor.size(), 8);
+    assert!(cpu.dev_mapper.find_by_name("sensor").is_none());
+    assert!(cpu.read_mem(SENSOR_ADDR, 1).is_err());
+    assert!(cpu.dev_mapper.unmap_by_name("sensor").is_none());
+
+    // A device that changes its size is reindexed
+    cpu.dev_mapper
+        .with_device_mut("RAM", |ram: &mut Ram| *ram = Ram::new(0x100))
+        .unwrap();
+    assert_eq!(cpu.dev_mapper.find_by_name("RAM").unwrap().end, 0x100);
+    assert!(cpu.read_mem(0x800, 1).is_err());
+    assert!(cpu
+        .dev_mapper
+        .with_device_mut("RAM", |_: &mut Rom| ())
+        .is_none());
+}
+
+// A timer that counts the cycles it was ticked for, reading it returns the count
+#[derive(Default)]
+struct Timer {
+    cycles: u64,
+    ticks: u64,
+    resets: u64,
+    base: Option<u64>,
+    // Raised on every tick
+    irq: Option<IrqLine>,
+}
+
+impl Device for Timer {
+    fn read(&self, addr: u64) -> Result<u8, BusFault> {
+        Ok((self.cycles >> (addr * 8)) as u8)
+    }
+
+    fn write(&mut self, addr: u64, _value: u8) -> Result<(), BusFault> {
+        Err(BusFault::write(addr))
+    }
+
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        self.ticks += 1;
+        if let Some(irq) = &self.irq {
+            irq.raise();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.cycles = 0;
+        self.resets += 1;
+    }
+
+    fn on_attach(&mut self, base: u64) {
+        self.base = Some(base);
+    }
+}
+
+impl Snapshot for Timer {}
+
+// Runs 5 NOPs and a HLT with a timer ticked every interval cycles
+fn timer_cpu(interval: u64) -> Cpu {
+    let mut program = [Nop as u8, Null as u8].repeat(5);
+    program.extend([Hlt as u8, Null as u8]);
+    let mut cpu = cpu(&program);
+    cpu.attach(
+        Box::new(Timer::default()),
+        String::from("timer"),
+        SENSOR_ADDR,
+    );
+    cpu.set_tick_interval(interval);
+    cpu.run().unwrap();
+    cpu
+}
+
+#[test]
+fn test_device_lifecycle() {
+    let mut cpu = timer_cpu(1);
+    assert_eq!(cpu.cycles(), 6);
+    let timer = cpu.dev_mapper.get::<Timer>("timer").unwrap();
+    assert_eq!((timer.cycles, timer.ticks), (6, 6));
+    assert_eq!(timer.base, Some(SENSOR_ADDR));
+    assert_eq!(
+        cpu.read_mem(SENSOR_ADDR, 8).unwrap(),
+        6u64.to_le_bytes().to_vec()
+    );
+
+    cpu.dev_mapper.remap("timer", 0x3000);
+    assert_eq!(
+        cpu.dev_mapper.get::<Timer>("timer").unwrap().base,
+        Some(0x3000)
+    );
+
+    cpu.reset_devices();
+    let timer = cpu.dev_mapper.get::<Timer>("timer").unwrap();
+    assert_eq!((timer.cycles, timer.resets), (0, 1));
+
+    // A coarser interval ticks less often with more cycles at a time, 0 stops the ticks
+    let cpu = timer_cpu(3);
+    let timer = cpu.dev_mapper.get::<Timer>("timer").unwrap();
+    assert_eq!((timer.cycles, timer.ticks), (6, 2));
+    let cpu = timer_cpu(0);
+    assert_eq!(cpu.cycles(), 6);
+    let timer = cpu.dev_mapper.get::<Timer>("timer").unwrap();
+    assert_eq!((timer.cycles, timer.ticks), (0, 0));
+}
+
+// Creates a CPU that enables interrupts and calls a subroutine at 0x30, interrupted by a timer every
+// 3 cycles whose handler counts the interrupts at 0xA00
+fn interrupted_call_cpu() -> Cpu {
+    let table = 0x800;
+    let program = [
+        &[Mov as u8, ImmToReg as u8][..],
+        &imm(table),
+        &[Register::VectorTable as u8],
+        &[Mov as u8, ImmToMem as u8],
+        &imm(0x40),
+        &imm(table + 8 * vectors::IRQ_BASE),
+        &[Sti as u8, Null as u8],
+        // 0x1f
+        &[Cal as u8, Literal as u8],
+        &imm(0x30),
+        // 0x29
+        &[Hlt as u8, Null as u8],
+        &[0; 5],
+        // 0x30
+        &[Nop as u8, Null as u8].repeat(4),
+        &[Ret as u8, Null as u8],
+        &[0; 6],
+        // 0x40
+        &[Inc as u8, Memory as u8],
+        &imm(0xA00),
+        &[Iret as u8, Null as u8],
+    ]
+    .concat();
+    let mut cpu = cpu(&program);
+    let timer = Timer {
+        irq: cpu.dev_mapper.irq_line(0),
+        ..Timer::default()
+    };
+    cpu.attach(Box::new(timer), String::from("timer"), SENSOR_ADDR);
+    cpu.set_tick_interval(3);
+    cpu
+}
+
+#[test]
+fn test_step_over_interrupted_call() {
+    // Stepping over the call returns to the caller, not from the first interrupt handler
+    let mut cpu = interrupted_call_cpu();
+    cpu.run_for(3).unwrap();
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0x1F);
+    assert_eq!(cpu.step_over().unwrap(), StopReason::Condition);
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0x29);
+    assert_eq!(cpu.backtrace().len(), 1);
+    assert!(cpu.read_mem(0xA00, 1).unwrap()[0] >= 2);
+
+    // Stepping out of the subroutine ignores the IRET of the handlers entered on the way
+    let mut cpu = interrupted_call_cpu();
+    cpu.run_until(|outcome| outcome.called()).unwrap();
+    assert_eq!(cpu.backtrace().len(), 2);
+    assert_eq!(cpu.step_out().unwrap(), StopReason::Condition);
+    assert_eq!(cpu.reg(Register::InstructionPointer), 0x29);
+    assert!(cpu.read_mem(0xA00, 1).unwrap()[0] >= 1);
+}
+
+#[test]
+fn test_replay_syscall_reading_device() {
+    // A syscall reads the timer into r0, then the guest reads it into r1
+    let program = [
+        &[Sys as u8, Literal as u8][..],
+        &imm(1),
+        &[Mov as u8, MemToReg as u8],
+        &imm(SENSOR_ADDR),
+        &[Register::Reg1 as u8],
+        &[Hlt as u8, Null as u8],
+    ]
+    .concat();
+    let timer_cpu = || {
+        let mut cpu = cpu(&program);
+        cpu.attach(
+            Box::new(Timer::default()),
+            String::from("timer"),
+            SENSOR_ADDR,
+        );
+        cpu
+    };
+
+    let mut recorded = timer_cpu();
+    recorded.register_syscall(
+        1,
+        Box::new(|ctx| {
+            let cycles = ctx.read64(SENSOR_ADDR)?;
+            ctx.set_reg(Register::Reg0, cycles);
+            Ok(())
+        }),
+    );
+    recorded.start_recording(DEFAULT_CHECKPOINT_INTERVAL);
+    assert_eq!(recorded.run().unwrap(), StopReason::Halted);
+    assert_eq!(recorded.reg(Register::Reg0), 0);
+    assert_eq!(recorded.reg(Register::Reg1), 1);
+    let log = recorded.stop_recording().unwrap();
+    assert_eq!(log.reads, vec![Ok(1)]);
+
+    // The read of the syscall is not in the log, so the guest read gets its own value
+    let mut replayed = timer_cpu();
+    replayed.replay(log, DEFAULT_CHECKPOINT_INTERVAL);
+    assert_eq!(replayed.run().unwrap(), StopReason::Halted);
+    assert_eq!(replayed.registers(), recorded.registers());
+}
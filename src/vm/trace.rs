@@ -0,0 +1,212 @@
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+use serde_json::json;
+
+use crate::{
+    dev_map::write_log::MemWrite,
+    opcodes::{Instruction, Opcode, OpcodeClass},
+    register::Register,
+    VmError,
+};
+
+// Magic bytes and version at the start of a binary trace
+pub const BINARY_MAGIC: &[u8; 4] = b"SLTR";
+pub const BINARY_VERSION: u8 = 1;
+
+// How a tracer writes the executed instructions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TraceFormat {
+    // One line of text an instruction
+    Text,
+    // One JSON object a line
+    JsonLines,
+    // Compact records after a header, see `Tracer::encode_binary`
+    Binary,
+}
+
+// An executed instruction and what it changed
+#[derive(Debug, Clone)]
+pub struct TraceRecord {
+    // Address of the instruction
+    pub addr: u64,
+    // The decoded instruction
+    pub instr: Instruction,
+    // The registers the instruction changed and their new values, except the instruction pointer
+    pub regs: Vec<(Register, u64)>,
+    // The memory the instruction wrote
+    pub writes: Vec<MemWrite>,
+    // The fault the instruction raised, if the guest did not handle it
+    pub fault: Option<VmError>,
+}
+
+// Writes a record of every executed instruction that passes its filters, until it reaches its size limit
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    range: Option<Range<u64>>,
+    classes: Option<Vec<OpcodeClass>>,
+    limit: Option<u64>,
+    written: u64,
+    truncated: bool,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self {
+            writer,
+            format,
+            range: None,
+            classes: None,
+            limit: None,
+            written: 0,
+            truncated: false,
+            error: None,
+        }
+    }
+
+    // Only trace the instructions at addresses in a range
+    pub fn with_range(mut self, range: Range<u64>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    // Only trace the instructions of some opcode classes
+    pub fn with_classes(mut self, classes: &[OpcodeClass]) -> Self {
+        self.classes = Some(classes.to_vec());
+        self
+    }
+
+    // Stop tracing before the trace grows past a number of bytes
+    pub fn with_limit(mut self, bytes: u64) -> Self {
+        self.limit = Some(bytes);
+        self
+    }
+
+    // Checks if the instruction at an address would be traced
+    pub fn traces(&self, addr: u64, opcode: Opcode) -> bool {
+        !self.truncated
+            && self.error.is_none()
+            && self
+                .range
+                .as_ref()
+                .is_none_or(|range| range.contains(&addr))
+            && self
+                .classes
+                .as_ref()
+                .is_none_or(|classes| classes.contains(&opcode.class()))
+    }
+
+    // Returns the number of bytes written
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    // Checks if records were dropped because the trace reached its size limit
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    // Writes a record, dropping it and every later one if it does not fit in the limit
+    pub fn record(&mut self, record: &TraceRecord) {
+        if self.truncated || self.error.is_some() {
+            return;
+        }
+
+        let mut bytes = Vec::new();
+        if self.format == TraceFormat::Binary && self.written == 0 {
+            bytes.extend_from_slice(BINARY_MAGIC);
+            bytes.push(BINARY_VERSION);
+        }
+        match self.format {
+            TraceFormat::Text => bytes.extend(Self::encode_text(record).into_bytes()),
+            TraceFormat::JsonLines => bytes.extend(Self::encode_json(record).into_bytes()),
+            TraceFormat::Binary => Self::encode_binary(record, &mut bytes),
+        }
+
+        let size = self.written + bytes.len() as u64;
+        if self.limit.is_some_and(|limit| size > limit) {
+            self.truncated = true;
+            return;
+        }
+        match self.writer.write_all(&bytes) {
+            Ok(()) => self.written = size,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    // Flushes the trace, returns the first error writing it
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.writer.flush(),
+        }
+    }
+
+    // Encodes a record like `0x0000000000000010  Add RegToReg: R0, R1  r1=0x3 [0xff0]=0x2a/8`
+    fn encode_text(record: &TraceRecord) -> String {
+        let mut line = format!("{:#018x}  {:?}", record.addr, record.instr);
+        for (reg, value) in record.regs.iter() {
+            line += &format!("  {}={:#x}", reg.mnemonic(), value);
+        }
+        for write in record.writes.iter() {
+            line += &format!("  [{:#x}]={:#x}/{}", write.addr, write.value, write.len);
+        }
+        if let Some(fault) = &record.fault {
+            line += &format!("  fault: {}", fault);
+        }
+        line + "\n"
+    }
+
+    // Encodes a record as a JSON object on its own line
+    fn encode_json(record: &TraceRecord) -> String {
+        let regs: serde_json::Map<String, serde_json::Value> = record
+            .regs
+            .iter()
+            .map(|(reg, value)| (reg.mnemonic().to_owned(), json!(value)))
+            .collect();
+        let writes: Vec<serde_json::Value> = record
+            .writes
+            .iter()
+            .map(|write| json!({ "addr": write.addr, "len": write.len, "value": write.value }))
+            .collect();
+
+        let mut object = json!({
+            "addr": record.addr,
+            "instr": format!("{:?}", record.instr),
+            "regs": regs,
+            "writes": writes,
+        });
+        if let Some(fault) = &record.fault {
+            object["fault"] = json!(fault.to_string());
+        }
+        object.to_string() + "\n"
+    }
+
+    // Encodes a record in little endian as the address (8 bytes), the opcode, the address mode,
+    // whether it faulted, the number of changed registers followed by each code and value (1 + 8 bytes),
+    // and the number of writes followed by each address, length and value (8 + 1 + 8 bytes).
+    // The operands are left out, they can be decoded from the image at the address
+    fn encode_binary(record: &TraceRecord, bytes: &mut Vec<u8>) {
+        bytes.extend(record.addr.to_le_bytes());
+        bytes.push(record.instr.opcode as u8);
+        bytes.push(record.instr.addr_mode as u8);
+        bytes.push(record.fault.is_some() as u8);
+
+        bytes.push(record.regs.len() as u8);
+        for (reg, value) in record.regs.iter() {
+            bytes.push(*reg as u8);
+            bytes.extend(value.to_le_bytes());
+        }
+
+        bytes.push(record.writes.len() as u8);
+        for write in record.writes.iter() {
+            bytes.extend(write.addr.to_le_bytes());
+            bytes.push(write.len as u8);
+            bytes.extend(write.value.to_le_bytes());
+        }
+    }
+}
@@ -0,0 +1,17 @@
+// Entries of the vector table the VT register points to, every entry is the 64-bit address of a handler.
+// A vector without a table or with a zero entry is not handled by the guest and stops the CPU instead.
+// Vectors below IRQ_BASE are reserved for faults, INT can enter any vector.
+
+// Raised by DIV, IDIV, MOD and IMOD with a zero divisor
+pub const DIVIDE_BY_ZERO: u64 = 0x00;
+
+// Raised by SYS with a number the host has no handler for
+pub const INVALID_SYSCALL: u64 = 0x01;
+
+// Raised by an access to an address where nothing is mapped or that the region does not allow, including
+// instruction fetches. The handler finds the faulting address in R0 and the kind of access in R1: 0 for a read,
+// 1 for a write and 2 for an instruction fetch
+pub const BUS_FAULT: u64 = 0x02;
+
+// The vector of IRQ line 0, line n enters vector IRQ_BASE + n
+pub const IRQ_BASE: u64 = 0x20;